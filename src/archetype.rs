@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    f32::consts::TAU,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use bevy::{asset::io::file::FileAssetReader, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bullet::{Accelerate, Homing, Wave, BULLET_LIFETIME},
+    level_gen::markers::DEFAULT_TURRET_ARCHETYPE,
+};
+
+/// where the game looks for turret archetypes, relative to the directory bevy
+/// loads the asset folder from (see `Archetypes::shipped_path`).
+pub const ARCHETYPES_PATH: &str = "assets/turrets.ron";
+
+/// the kinds of turrets there are, by name. each `Turret` refers to one of these,
+/// so new kinds of turrets can be added without changing any code.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Archetypes {
+    pub turrets: HashMap<String, TurretArchetype>,
+}
+
+/// what a kind of turret fires, and how.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TurretArchetype {
+    pub bullet: BulletArchetype,
+    #[serde(default)]
+    pub pattern: FirePattern,
+}
+
+/// the bullets a turret fires every time it fires. angles are in radians.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum FirePattern {
+    /// one bullet at the player.
+    #[default]
+    Single,
+    /// `count` bullets fanned out over `angle`, centered on the player.
+    Spread { count: u32, angle: f32 },
+    /// `shots` bullets at the player one after the other, `interval` seconds apart.
+    Burst { shots: u32, interval: f32 },
+    /// `arms` bullets evenly around the turret, turning by `turn` every time it fires.
+    Spiral { arms: u32, turn: f32 },
+    /// `count` bullets evenly around the turret, starting at the player.
+    Ring { count: u32 },
+}
+
+impl FirePattern {
+    /// whether the turret has to be pointing at the player to fire.
+    pub fn is_aimed(&self) -> bool {
+        !matches!(self, FirePattern::Spiral { .. } | FirePattern::Ring { .. })
+    }
+
+    /// the directions to fire bullets in, when aiming along `aim` and having fired `volley`
+    /// times before.
+    pub fn directions(&self, aim: Vec2, volley: u32) -> Vec<Vec2> {
+        let around = |count: u32, start: Vec2| {
+            (0..count)
+                .map(|i| Vec2::from_angle(TAU * i as f32 / count as f32).rotate(start))
+                .collect()
+        };
+        match *self {
+            FirePattern::Single | FirePattern::Burst { .. } => vec![aim],
+            FirePattern::Spread { count: 1, .. } => vec![aim],
+            FirePattern::Spread { count, angle } => (0..count)
+                .map(|i| {
+                    let offset = angle * (i as f32 / (count - 1) as f32 - 0.5);
+                    Vec2::from_angle(offset).rotate(aim)
+                })
+                .collect(),
+            FirePattern::Spiral { arms, turn } => {
+                around(arms, Vec2::from_angle(turn * volley as f32))
+            }
+            FirePattern::Ring { count } => around(count, aim),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BulletArchetype {
+    pub speed: f32,
+    pub dmg: f32,
+    pub radius: f32,
+    pub color: Color,
+    /// how many times the bullet bounces off of walls before it breaks on one.
+    #[serde(default)]
+    pub max_bounces: u32,
+    /// seconds the bullet lasts for if it doesn't hit anything.
+    #[serde(default = "default_lifetime")]
+    pub lifetime: f32,
+    #[serde(default)]
+    pub homing: Option<Homing>,
+    #[serde(default)]
+    pub wave: Option<Wave>,
+    #[serde(default)]
+    pub accelerate: Option<Accelerate>,
+}
+
+fn default_lifetime() -> f32 {
+    BULLET_LIFETIME
+}
+
+impl Default for Archetypes {
+    /// just the default archetype, which fires single bullets straight at the player.
+    fn default() -> Self {
+        let turret = TurretArchetype {
+            bullet: BulletArchetype {
+                speed: 200.0,
+                dmg: 0.1,
+                radius: 6.0,
+                color: Color::RED,
+                max_bounces: 0,
+                lifetime: BULLET_LIFETIME,
+                homing: None,
+                wave: None,
+                accelerate: None,
+            },
+            pattern: FirePattern::Single,
+        };
+        Self {
+            turrets: HashMap::from([(DEFAULT_TURRET_ARCHETYPE.to_string(), turret)]),
+        }
+    }
+}
+
+impl Archetypes {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read archetypes {}", path.display()))?;
+        let archetypes: Self = ron::from_str(&text)
+            .with_context(|| format!("failed to parse archetypes {}", path.display()))?;
+        archetypes
+            .check()
+            .with_context(|| format!("invalid archetypes {}", path.display()))?;
+        Ok(archetypes)
+    }
+
+    /// catch values bullets can't move with, such as a speed range that's upside down.
+    fn check(&self) -> anyhow::Result<()> {
+        for (name, turret) in &self.turrets {
+            let bullet = &turret.bullet;
+            if let Some(Homing { turn_rate, .. }) = bullet.homing {
+                if !(turn_rate.is_finite() && turn_rate >= 0.0) {
+                    return Err(anyhow!(
+                        "'{}' homes with a turn rate of {}",
+                        name,
+                        turn_rate
+                    ));
+                }
+            }
+            if let Some(Accelerate {
+                min_speed,
+                max_speed,
+                ..
+            }) = bullet.accelerate
+            {
+                if !(min_speed.is_finite() && max_speed.is_finite() && min_speed <= max_speed) {
+                    return Err(anyhow!(
+                        "'{}' accelerates between speeds {} and {}",
+                        name,
+                        min_speed,
+                        max_speed
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `ARCHETYPES_PATH`, resolved the same way bevy resolves asset paths: relative to
+    /// `BEVY_ASSET_ROOT` or `CARGO_MANIFEST_DIR` if one is set, otherwise to the executable.
+    pub fn shipped_path() -> PathBuf {
+        FileAssetReader::get_base_path().join(ARCHETYPES_PATH)
+    }
+
+    /// takes `--archetypes <path>` or `--default-archetypes` out of the command line arguments
+    /// and loads the archetypes they ask for. without either, the archetypes are loaded from
+    /// `shipped_path`, and a missing file is an error. `--default-archetypes` skips loading,
+    /// leaving turrets with only the default archetype.
+    pub fn from_args(args: &mut Vec<String>) -> anyhow::Result<Self> {
+        let mut path = Some(Self::shipped_path());
+        while let Some(i) = args
+            .iter()
+            .position(|arg| arg == "--archetypes" || arg == "--default-archetypes")
+        {
+            if args.remove(i) == "--default-archetypes" {
+                path = None;
+            } else if i < args.len() {
+                path = Some(args.remove(i).into());
+            } else {
+                return Err(anyhow!("missing value for argument --archetypes"));
+            }
+        }
+        match path {
+            Some(path) => Self::load(&path)
+                .context("use --default-archetypes to play with only the default archetype"),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn turret(&self, name: &str) -> Option<&TurretArchetype> {
+        self.turrets.get(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn archetypes_come_from_the_arguments() {
+        let mut rest = args(&["--seed", "3", "--default-archetypes"]);
+        assert_eq!(Archetypes::from_args(&mut rest).unwrap(), Archetypes::default());
+        assert_eq!(rest, args(&["--seed", "3"]));
+
+        let shipped = Archetypes::load(&Archetypes::shipped_path()).unwrap();
+        let path = Archetypes::shipped_path().display().to_string();
+        assert_eq!(Archetypes::from_args(&mut args(&["--archetypes", &path])).unwrap(), shipped);
+        assert_eq!(Archetypes::from_args(&mut vec![]).unwrap(), shipped);
+
+        assert!(Archetypes::from_args(&mut args(&["--archetypes", "missing.ron"])).is_err());
+        assert!(Archetypes::from_args(&mut args(&["--archetypes"])).is_err());
+    }
+
+    #[test]
+    fn bad_speed_ranges_dont_load() {
+        let path =
+            std::env::temp_dir().join(format!("trajectory-archetypes-{}.ron", std::process::id()));
+        let load = |turret: &TurretArchetype| {
+            let mut archetypes = Archetypes::default();
+            archetypes.turrets.insert("bad".to_string(), turret.clone());
+            fs::write(&path, ron::to_string(&archetypes).unwrap()).unwrap();
+            Archetypes::load(&path)
+        };
+        let mut turret = Archetypes::default().turrets[DEFAULT_TURRET_ARCHETYPE].clone();
+        turret.bullet.accelerate = Some(Accelerate {
+            rate: 10.0,
+            min_speed: 300.0,
+            max_speed: 100.0,
+        });
+        assert!(load(&turret).is_err());
+        turret.bullet.accelerate = Some(Accelerate {
+            rate: 10.0,
+            min_speed: f32::NAN,
+            max_speed: 100.0,
+        });
+        assert!(load(&turret).is_err());
+
+        turret.bullet.accelerate = None;
+        turret.bullet.homing = Some(Homing {
+            turn_rate: -1.0,
+            lifetime: 1.0,
+        });
+        assert!(load(&turret).is_err());
+        turret.bullet.homing = Some(Homing {
+            turn_rate: 1.0,
+            lifetime: 1.0,
+        });
+        assert!(load(&turret).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shipped_archetypes_load() {
+        let archetypes = Archetypes::load(&Archetypes::shipped_path()).unwrap();
+        assert!(archetypes.turret(DEFAULT_TURRET_ARCHETYPE).is_some());
+    }
+
+    #[test]
+    fn patterns_fire_in_the_right_directions() {
+        let close = |a: Vec2, b: Vec2| a.distance(b) < 1e-5;
+        let aim = Vec2::Y;
+        let spread = FirePattern::Spread {
+            count: 3,
+            angle: TAU / 2.0,
+        };
+        let dirs = spread.directions(aim, 0);
+        assert!(close(dirs[0], Vec2::X) && close(dirs[1], aim) && close(dirs[2], -Vec2::X));
+
+        let dirs = FirePattern::Ring { count: 4 }.directions(aim, 7);
+        assert!(close(dirs[0], aim) && close(dirs[2], -aim));
+
+        let spiral = FirePattern::Spiral {
+            arms: 2,
+            turn: TAU / 4.0,
+        };
+        assert!(close(spiral.directions(aim, 0)[0], Vec2::X));
+        assert!(close(spiral.directions(aim, 1)[0], Vec2::Y));
+        assert!(close(spiral.directions(aim, 1)[1], -Vec2::Y));
+    }
+}
@@ -1,15 +1,68 @@
+use std::{
+    collections::HashMap,
+    f32::consts::{PI, TAU},
+};
+
 use bevy::{
+    ecs::system::SystemParam,
     math::{Vec2, Vec3},
     prelude::*,
     render::{
         mesh::{Indices, Mesh},
         render_resource::PrimitiveTopology,
     },
+    sprite::MaterialMesh2dBundle,
 };
-use bevy_rapier2d::{plugin::RapierContext, geometry::Collider, pipeline::QueryFilter};
+use bevy_rapier2d::{plugin::RapierContext, geometry::Collider};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archetype::{Archetypes, BulletArchetype},
+    fixed_step::Interpolated,
+    health::{damage_player, Dead, PlayerDamaged},
+    player::{bounce_off_hit, Player},
+    walls, GameSet, Hazard,
+};
+
+/// radius of the bullets `bullet_mesh` is the right size for. bigger or smaller bullets
+/// have their mesh scaled to fit.
+const BULLET_MESH_RADIUS: f32 = 6.0;
+/// seconds bullets last for, unless their archetype says otherwise.
+pub const BULLET_LIFETIME: f32 = 10.0;
+/// bullets this far away from the player are removed, since they're long off screen.
+pub const BULLET_CULL_DISTANCE: f32 = 2000.0;
+
+/// steers and moves bullets, and hits the player with them.
+pub struct BulletPlugin;
 
-use crate::player::Player;
+impl Plugin for BulletPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BulletPool>()
+            .init_resource::<BulletAssets>()
+            .add_systems(
+                PreUpdate,
+                load_bullet_assets.run_if(resource_exists_and_changed::<Archetypes>()),
+            )
+            .configure_sets(
+                FixedUpdate,
+                GameSet::Bullets
+                    .after(GameSet::Player)
+                    .after(GameSet::Turrets),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    home_bullets,
+                    wave_bullets,
+                    accelerate_bullets,
+                    bullet_system,
+                )
+                    .chain()
+                    .in_set(GameSet::Bullets),
+            );
+    }
+}
 
 pub fn bullet_mesh() -> Mesh {
     let scale = 10.0;
@@ -41,44 +94,261 @@ pub struct Bullet {
     pub dir: Vec2,
     pub speed: f32,
     pub radius: f32,
+    /// how many more times the bullet bounces off of walls before it breaks on one.
+    pub bounces_left: u32,
+    /// seconds left until the bullet disappears.
+    pub lifetime: f32,
+}
+
+/// bullets that were removed, which `BulletSpawner` reuses instead of spawning new entities.
+/// they keep their transform and rendering components, but are hidden.
+#[derive(Resource, Debug, Default)]
+pub struct BulletPool(pub Vec<Entity>);
+
+/// the assets bullets are drawn with. every bullet shares the same mesh, and the bullets
+/// of each turret archetype share a material, by archetype name.
+#[derive(Resource, Debug, Default)]
+pub struct BulletAssets {
+    mesh: Handle<Mesh>,
+    materials: HashMap<String, Handle<ColorMaterial>>,
+}
+
+/// build the bullet assets for the archetypes, whenever they change.
+pub fn load_bullet_assets(
+    archetypes: Res<Archetypes>,
+    mut assets: ResMut<BulletAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if !meshes.contains(&assets.mesh) {
+        assets.mesh = meshes.add(bullet_mesh());
+    }
+    assets.materials = archetypes
+        .turrets
+        .iter()
+        .map(|(name, turret)| {
+            let material = materials.add(ColorMaterial::from(turret.bullet.color));
+            (name.clone(), material)
+        })
+        .collect();
+}
+
+/// take the bullet out of play, and put it in the pool to be used again.
+pub fn remove_bullet(commands: &mut Commands, pool: &mut BulletPool, bullet: Entity) {
+    commands
+        .entity(bullet)
+        .remove::<(Bullet, Hazard, Homing, Wave, Accelerate)>()
+        .insert(Visibility::Hidden);
+    pool.0.push(bullet);
+}
+
+/// turns a bullet towards the player, for a while.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Homing {
+    /// radians per second.
+    pub turn_rate: f32,
+    /// seconds the bullet keeps homing for, after which it flies straight.
+    pub lifetime: f32,
+}
+
+/// makes a bullet weave from side to side around the direction it was fired in.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Wave {
+    /// the most the bullet turns away from its course, in radians.
+    pub amplitude: f32,
+    /// waves per second.
+    pub frequency: f32,
+    /// seconds since the bullet was fired.
+    #[serde(skip)]
+    pub time: f32,
+}
+
+/// speeds a bullet up, or slows it down when `rate` is negative.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Accelerate {
+    /// change in speed per second.
+    pub rate: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+}
+
+/// everything needed to spawn bullets.
+#[derive(SystemParam)]
+pub struct BulletSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    assets: Res<'w, BulletAssets>,
+    pool: ResMut<'w, BulletPool>,
+}
+
+impl BulletSpawner<'_, '_> {
+    /// spawn a bullet of the archetype at `pos`, flying along `dir`. `name` is the name of
+    /// the turret archetype the bullet belongs to, which picks its material.
+    /// this reuses a bullet from the pool if there is one.
+    pub fn spawn(
+        &mut self,
+        name: &str,
+        archetype: &BulletArchetype,
+        pos: Vec3,
+        dir: Vec2,
+    ) -> Entity {
+        let bullet = Bullet {
+            dmg: archetype.dmg,
+            dir,
+            speed: archetype.speed,
+            radius: archetype.radius,
+            bounces_left: archetype.max_bounces,
+            lifetime: archetype.lifetime,
+        };
+        let transform = Transform::from_translation(pos)
+            .with_rotation(Quat::from_rotation_z(dir.y.atan2(dir.x) - PI / 2.0))
+            .with_scale(Vec3::splat(archetype.radius / BULLET_MESH_RADIUS));
+        let components = (
+            MaterialMesh2dBundle {
+                mesh: self.assets.mesh.clone().into(),
+                material: self.assets.materials.get(name).cloned().unwrap_or_default(),
+                transform,
+                ..default()
+            },
+            Hazard {
+                radius: bullet.radius,
+            },
+            bullet,
+            Interpolated::new(transform),
+        );
+        let pooled = std::iter::from_fn(|| self.pool.0.pop())
+            .find(|&entity| self.commands.get_entity(entity).is_some());
+        let mut entity = match pooled {
+            Some(entity) => {
+                let mut entity = self.commands.entity(entity);
+                entity.insert(components);
+                entity
+            }
+            None => self.commands.spawn(components),
+        };
+        if let Some(homing) = archetype.homing {
+            entity.insert(homing);
+        }
+        if let Some(wave) = archetype.wave {
+            entity.insert(wave);
+        }
+        if let Some(accelerate) = archetype.accelerate {
+            entity.insert(accelerate);
+        }
+        entity.id()
+    }
+}
+
+pub fn home_bullets(
+    time: Res<Time<Fixed>>,
+    player: Query<&Transform, (With<Player>, Without<Dead>)>,
+    mut bullets: Query<(&Transform, &mut Bullet, &mut Homing), Without<Player>>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    for (transform, mut bullet, mut homing) in &mut bullets {
+        if homing.lifetime <= 0.0 {
+            continue;
+        }
+        homing.lifetime -= time.delta_seconds();
+        let to_player = (player.translation - transform.translation).truncate();
+        if to_player == Vec2::ZERO {
+            continue;
+        }
+        let max_turn = homing.turn_rate * time.delta_seconds();
+        let turn = bullet
+            .dir
+            .angle_between(to_player)
+            .clamp(-max_turn, max_turn);
+        bullet.dir = Vec2::from_angle(turn).rotate(bullet.dir);
+    }
+}
+
+pub fn wave_bullets(time: Res<Time<Fixed>>, mut bullets: Query<(&mut Bullet, &mut Wave)>) {
+    for (mut bullet, mut wave) in &mut bullets {
+        let (amplitude, frequency) = (wave.amplitude, wave.frequency);
+        let offset = |t: f32| amplitude * (TAU * frequency * t).sin();
+        let before = offset(wave.time);
+        wave.time += time.delta_seconds();
+        let turn = offset(wave.time) - before;
+        bullet.dir = Vec2::from_angle(turn).rotate(bullet.dir);
+    }
+}
+
+pub fn accelerate_bullets(time: Res<Time<Fixed>>, mut bullets: Query<(&mut Bullet, &Accelerate)>) {
+    for (mut bullet, accelerate) in &mut bullets {
+        bullet.speed = (bullet.speed + accelerate.rate * time.delta_seconds())
+            .clamp(accelerate.min_speed, accelerate.max_speed);
+    }
 }
 
 pub fn bullet_system(
     rapier_context: Res<RapierContext>,
-    time: Res<Time<Virtual>>,
+    time: Res<Time<Fixed>>,
     mut bullets: Query<(Entity, &mut Transform, &mut Bullet)>,
     mut player: Query<(&mut Transform, &mut Player), Without<Bullet>>,
+    mut damaged: EventWriter<PlayerDamaged>,
+    mut pool: ResMut<BulletPool>,
     mut commands: Commands,
 ) {
-    let (player_transform, mut player) = player.single_mut();
-    bullets.for_each_mut(|(bullet_entity, mut bullet_transform, bullet)| {
-        let rad = bullet.radius + player.radius;
-        if bullet_transform
+    let Ok((player_transform, mut player)) = player.get_single_mut() else {
+        return;
+    };
+    bullets.for_each_mut(|(bullet_entity, mut bullet_transform, mut bullet)| {
+        let player_dist = bullet_transform
             .translation
-            .distance_squared(player_transform.translation)
-            < rad * rad
-        {
-            player.health -= bullet.dmg;
-            commands.entity(bullet_entity).despawn();
+            .truncate()
+            .distance(player_transform.translation.truncate());
+        bullet.lifetime -= time.delta_seconds();
+        if bullet.lifetime <= 0.0 || player_dist > BULLET_CULL_DISTANCE {
+            remove_bullet(&mut commands, &mut pool, bullet_entity);
             return;
         }
 
-        if rapier_context.intersection_with_shape(
-            bullet_transform.translation.xy().into(),
-            0.0,
-            &Collider::ball(bullet.radius),
-            QueryFilter::new(),
-        ).is_some() {
-            commands.entity(bullet_entity).despawn();
+        if player_dist < bullet.radius + player.radius
+            // bullets go through the player while it can't be hurt, or is dead.
+            && damage_player(&mut player, bullet.dmg, &mut damaged)
+        {
+            remove_bullet(&mut commands, &mut pool, bullet_entity);
             return;
         }
 
-        let delta = bullet.dir.normalize() * bullet.speed * time.delta_seconds();
-        bullet_transform.translation += Vec3::new(delta.x, delta.y, 0.0);
+        let pos = bullet_transform.translation.truncate();
+        let dir = bullet.dir.normalize();
+        let dist = bullet.speed * time.delta_seconds();
+        let hit = rapier_context.cast_shape(
+            pos,
+            0.0,
+            dir,
+            &Collider::ball(bullet.radius),
+            dist,
+            false,
+            walls(),
+        );
+        let new_pos = match hit {
+            None => pos + dir * dist,
+            // bullets that are out of bounces break on the wall they hit.
+            Some(_) if bullet.bounces_left == 0 => {
+                remove_bullet(&mut commands, &mut pool, bullet_entity);
+                return;
+            }
+            Some((_, hit)) => match bounce_off_hit(&rapier_context, pos, dir, &hit) {
+                Some(bounce) => {
+                    bullet.bounces_left -= 1;
+                    bullet.dir = bounce.dir;
+                    bounce.pt
+                }
+                None => {
+                    remove_bullet(&mut commands, &mut pool, bullet_entity);
+                    return;
+                }
+            },
+        };
+        bullet_transform.translation = new_pos.extend(bullet_transform.translation.z);
 
         bullet_transform.rotation = Quat::from_axis_angle(
             Vec3::new(0., 0., 1.),
-            bullet.dir.y.atan2(bullet.dir.x) - std::f32::consts::PI / 2.0,
+            bullet.dir.y.atan2(bullet.dir.x) - PI / 2.0,
         );
     });
 }
@@ -0,0 +1,40 @@
+use bevy::prelude::*;
+
+use crate::{
+    mouse::{mouse_world_coords, MouseWorldCoords},
+    player::Player,
+    GameSet,
+};
+
+/// the camera, which follows the player, and the mouse position in the world.
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MouseWorldCoords>()
+            .configure_sets(Update, GameSet::Camera.after(GameSet::Player))
+            .add_systems(Startup, setup_camera)
+            .add_systems(Update, mouse_world_coords.in_set(GameSet::Input))
+            .add_systems(Update, (zoom, camera_follow).in_set(GameSet::Camera));
+    }
+}
+
+fn setup_camera(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+}
+
+fn zoom(mut query: Query<&mut OrthographicProjection, With<Camera2d>>) {
+    for mut projection in &mut query {
+        projection.scale = 1.0;
+    }
+}
+
+fn camera_follow(
+    mut cameras: Query<&mut Transform, With<Camera>>,
+    players: Query<&Transform, (With<Player>, Without<Camera>)>,
+) {
+    let (Ok(player), Ok(mut transform)) = (players.get_single(), cameras.get_single_mut()) else {
+        return;
+    };
+    transform.translation = player.translation;
+}
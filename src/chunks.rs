@@ -0,0 +1,179 @@
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+use noise::{Fbm, Simplex};
+
+use crate::{
+    level::{spawn_terrain, Level, LevelConfig},
+    level_gen::{
+        marching_squares::marching_squares_in,
+        markers::{Marker, MarkerKind},
+        regions::{find_spawn, Regions},
+        tiles::Tiles,
+    },
+};
+
+/// controls how the endless level is split up and streamed in around the camera.
+#[derive(Resource, Debug, Clone)]
+pub struct ChunkConfig {
+    /// number of tiles along each side of a chunk.
+    pub chunk_size: usize,
+    /// chunks within this many chunks of the camera are loaded.
+    pub load_radius: i32,
+    /// chunks further than this many chunks away from the camera are despawned.
+    /// this is larger than `load_radius` so chunks on the border don't keep getting rebuilt.
+    pub unload_radius: i32,
+    /// the maximum number of chunks built in a single frame.
+    pub max_chunks_per_frame: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 32,
+            load_radius: 2,
+            unload_radius: 3,
+            max_chunks_per_frame: 2,
+        }
+    }
+}
+
+/// a square piece of the endless level, identified by its chunk coordinates.
+/// chunk (x, y) contains the tiles from node (x, y) * chunk_size
+/// up to (but not including) node (x + 1, y + 1) * chunk_size.
+#[derive(Component, Debug)]
+pub struct Chunk {
+    pub coord: IVec2,
+}
+
+/// the chunks that are currently spawned.
+#[derive(Resource, Default)]
+pub struct LoadedChunks(pub HashMap<IVec2, Entity>);
+
+/// the noise function the endless level is sampled from.
+#[derive(Resource)]
+pub struct ChunkNoise(pub Fbm<Simplex>);
+
+/// sample the densities needed to build the chunk at `coord`. the returned tiles
+/// start one node before the chunk and end two nodes after it (the margin `marching_squares_in`
+/// needs), so node (1, 1) of the tiles is the first node of the chunk.
+pub fn chunk_tiles(config: &LevelConfig, noise: &Fbm<Simplex>, chunk_size: usize, coord: IVec2) -> Tiles {
+    let origin = coord * chunk_size as i32 - IVec2::ONE;
+    let dim = [chunk_size + 3, chunk_size + 3];
+    Tiles::new(
+        config.noise_densities_at(noise, origin, dim),
+        config.node_spacing,
+    )
+}
+
+/// sets up the endless level, and places the player spawn in the most open spot
+/// close to the origin so the player doesn't start inside of a wall.
+pub fn setup_chunks(
+    mut commands: Commands,
+    config: Res<LevelConfig>,
+    chunk_config: Res<ChunkConfig>,
+) {
+    let noise = config.noise();
+    let size = chunk_config.chunk_size;
+    let origin = -IVec2::splat(size as i32);
+    let tiles = Tiles::new(
+        config.noise_densities_at(&noise, origin, [size * 2, size * 2]),
+        config.node_spacing,
+    );
+    let markers = find_spawn(&tiles, &Regions::new(&tiles))
+        .map(|pos| Marker {
+            pos: pos + origin.as_vec2(),
+            kind: MarkerKind::PlayerSpawn,
+        })
+        .into_iter()
+        .collect();
+
+    commands.insert_resource(Level {
+        dimensions: [0, 0],
+        node_spacing: config.node_spacing,
+        markers,
+    });
+    commands.insert_resource(ChunkNoise(noise));
+    commands.init_resource::<LoadedChunks>();
+}
+
+/// everything needed to build chunks and spawn them.
+#[derive(SystemParam)]
+pub struct ChunkSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    config: Res<'w, LevelConfig>,
+    chunk_config: Res<'w, ChunkConfig>,
+    noise: Res<'w, ChunkNoise>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<ColorMaterial>>,
+}
+
+impl ChunkSpawner<'_, '_> {
+    /// build the terrain of the chunk at `coord` and spawn it.
+    pub fn spawn(&mut self, coord: IVec2) -> Entity {
+        let size = self.chunk_config.chunk_size as i32;
+        let tiles = chunk_tiles(&self.config, &self.noise.0, self.chunk_config.chunk_size, coord);
+        let (mesh, contours) =
+            marching_squares_in(&tiles, [1, 1].into(), [size + 1, size + 1].into());
+        // node (0, 0) of the tiles is one node before the chunk.
+        let origin = coord * size - IVec2::ONE;
+        let translation =
+            Vec2::new(origin.x as f32, -origin.y as f32) * self.config.node_spacing as f32;
+        let entity = spawn_terrain(
+            &mut self.commands,
+            &mut self.meshes,
+            &mut self.materials,
+            mesh,
+            contours,
+            Transform::from_translation(translation.extend(0.0)),
+        );
+        self.commands.entity(entity).insert(Chunk { coord });
+        entity
+    }
+
+    pub fn despawn(&mut self, chunk: Entity) {
+        self.commands.entity(chunk).despawn();
+    }
+}
+
+/// spawns chunks as the camera approaches them, and despawns them once it moves away.
+pub fn stream_chunks(
+    config: Res<LevelConfig>,
+    chunk_config: Res<ChunkConfig>,
+    mut loaded: ResMut<LoadedChunks>,
+    camera: Query<&Transform, With<Camera>>,
+    mut chunks: ChunkSpawner,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let size = chunk_config.chunk_size as i32;
+    let chunk_world_size = config.node_spacing as f32 * size as f32;
+    // nodes are rendered with the y axis pointing down.
+    let center = (Vec2::new(camera.translation.x, -camera.translation.y) / chunk_world_size)
+        .floor()
+        .as_ivec2();
+
+    loaded.0.retain(|coord, entity| {
+        let dist = (*coord - center).abs();
+        if dist.x.max(dist.y) > chunk_config.unload_radius {
+            chunks.despawn(*entity);
+            false
+        } else {
+            true
+        }
+    });
+
+    let radius = chunk_config.load_radius;
+    let mut missing: Vec<IVec2> = (-radius..=radius)
+        .flat_map(|y| (-radius..=radius).map(move |x| center + IVec2::new(x, y)))
+        .filter(|coord| !loaded.0.contains_key(coord))
+        .collect();
+    // build the chunks closest to the camera first.
+    missing.sort_by_key(|coord| {
+        let diff = *coord - center;
+        diff.x * diff.x + diff.y * diff.y
+    });
+
+    for coord in missing.into_iter().take(chunk_config.max_chunks_per_frame) {
+        loaded.0.insert(coord, chunks.spawn(coord));
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::GameSet;
+
+/// length of a simulation step, in virtual time. since fixed steps are paid for
+/// with virtual time, fewer steps run per second while time is slowed down.
+pub const TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// runs the simulation sets (player, turrets, bullets and health) in `FixedUpdate` at `TIMESTEP`,
+/// and smooths out the movement of `Interpolated` entities between steps.
+pub struct FixedStepPlugin;
+
+impl Plugin for FixedStepPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_duration(TIMESTEP))
+            .init_resource::<StepCount>()
+            .add_systems(
+                FixedUpdate,
+                (
+                    restore_transforms
+                        .before(GameSet::Player)
+                        .before(GameSet::Turrets)
+                        .before(GameSet::Bullets)
+                        .before(GameSet::Health),
+                    (record_transforms, count_step)
+                        .after(GameSet::Player)
+                        .after(GameSet::Turrets)
+                        .after(GameSet::Bullets)
+                        .after(GameSet::Health),
+                ),
+            )
+            .add_systems(Update, interpolate_transforms.before(GameSet::Camera));
+    }
+}
+
+/// number of fixed steps that have run so far.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StepCount(pub u64);
+
+/// the transform of a simulated entity at the end of the last two fixed steps.
+/// fixed step systems move the entity through its `Transform` as usual, but between
+/// steps the transform is replaced with one interpolated between these two,
+/// so movement looks smooth no matter how many steps ran in a frame.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Interpolated {
+    pub previous: Transform,
+    pub current: Transform,
+}
+
+impl Interpolated {
+    pub fn new(transform: Transform) -> Self {
+        Self {
+            previous: transform,
+            current: transform,
+        }
+    }
+
+    /// move the entity without interpolating from where it was.
+    /// this has to be used instead of setting the transform outside of fixed steps.
+    pub fn teleport(&mut self, transform: Transform) {
+        *self = Self::new(transform);
+    }
+}
+
+/// put back the transforms the last step ended with, so the simulation
+/// doesn't continue from the interpolated ones.
+fn restore_transforms(mut query: Query<(&mut Transform, &Interpolated)>) {
+    for (mut transform, interpolated) in &mut query {
+        *transform = interpolated.current;
+    }
+}
+
+fn record_transforms(mut query: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in &mut query {
+        interpolated.previous = interpolated.current;
+        interpolated.current = *transform;
+    }
+}
+
+fn count_step(mut count: ResMut<StepCount>) {
+    count.0 += 1;
+}
+
+/// place entities in between the transforms of the last two steps, based on how much
+/// time has built up towards the next step.
+fn interpolate_transforms(
+    fixed: Res<Time<Fixed>>,
+    mut query: Query<(&mut Transform, &Interpolated)>,
+) {
+    let t = fixed.overstep_percentage().clamp(0.0, 1.0);
+    for (mut transform, Interpolated { previous, current }) in &mut query {
+        transform.translation = previous.translation.lerp(current.translation, t);
+        transform.rotation = previous.rotation.slerp(current.rotation, t);
+        transform.scale = previous.scale.lerp(current.scale, t);
+    }
+}
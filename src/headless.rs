@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use bevy::{
+    input::{mouse::MouseButtonInput, ButtonState, InputPlugin},
+    prelude::*,
+    time::TimeUpdateStrategy,
+};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    fixed_step::{StepCount, TIMESTEP}, level::LevelConfig, mouse::MouseWorldCoords, player::Player,
+    turret::Turret, GamePlugin,
+};
+
+/// how much time passes in every frame of a headless app by default,
+/// which runs one fixed step per frame while time isn't slowed down.
+pub const FRAME_TIME: Duration = TIMESTEP;
+
+/// the game without a window or renderer, so it can be stepped frame by frame
+/// in tests and tools on machines without a gpu. time advances by the same amount every frame,
+/// and mouse input is faked by setting `MouseWorldCoords` and sending button events.
+/// more plugins can be added to `app` until the first update.
+pub struct Headless {
+    pub app: App,
+    started: bool,
+}
+
+impl Headless {
+    pub fn new(level_config: LevelConfig) -> Self {
+        Self::with_frame_time(level_config, FRAME_TIME)
+    }
+
+    /// a headless app where `frame_time` passes every frame.
+    pub fn with_frame_time(level_config: LevelConfig, frame_time: Duration) -> Self {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            InputPlugin,
+            AssetPlugin::default(),
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(1.0),
+            GamePlugin::new(level_config).expect("failed to build the level"),
+        ))
+        // the level builds meshes for rendering even when nothing draws them.
+        .init_asset::<Mesh>()
+        .init_asset::<ColorMaterial>()
+        .insert_resource(TimeUpdateStrategy::ManualDuration(frame_time));
+        Self {
+            app,
+            started: false,
+        }
+    }
+
+    fn update(&mut self) {
+        if !self.started {
+            // `App::run` would normally do this before the first update.
+            self.app.finish();
+            self.app.cleanup();
+            self.started = true;
+        }
+        self.app.update();
+    }
+
+    /// run `frames` updates of the app. the first update also runs the startup systems.
+    pub fn step(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.update();
+        }
+    }
+
+    /// keep stepping until `done` returns true, for at most `max_frames` frames.
+    /// returns whether `done` returned true.
+    pub fn step_until(
+        &mut self,
+        max_frames: usize,
+        mut done: impl FnMut(&mut World) -> bool,
+    ) -> bool {
+        for _ in 0..max_frames {
+            self.update();
+            if done(&mut self.app.world) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// number of fixed steps the simulation has run.
+    pub fn fixed_steps(&self) -> u64 {
+        self.app.world.resource::<StepCount>().0
+    }
+
+    /// keep stepping until at least `steps` more fixed steps have run. frames can run more
+    /// than one step, so this can overshoot. panics if time is so slow the steps never run.
+    pub fn step_fixed(&mut self, steps: u64) {
+        let target = self.fixed_steps() + steps;
+        let done = self.step_until(100_000, |world| world.resource::<StepCount>().0 >= target);
+        assert!(done, "only ran {} of {} steps", self.fixed_steps(), target);
+    }
+
+    /// move the fake mouse to `pos` in world space.
+    pub fn set_mouse(&mut self, pos: Vec2) {
+        self.app.world.resource_mut::<MouseWorldCoords>().0 = pos;
+    }
+
+    /// press a mouse button, it's seen as pressed from the next frame on.
+    pub fn press(&mut self, button: MouseButton) {
+        self.send_button(button, ButtonState::Pressed);
+    }
+
+    pub fn release(&mut self, button: MouseButton) {
+        self.send_button(button, ButtonState::Released);
+    }
+
+    /// press and release a mouse button within the next frame.
+    pub fn click(&mut self, button: MouseButton) {
+        self.press(button);
+        self.release(button);
+    }
+
+    fn send_button(&mut self, button: MouseButton, state: ButtonState) {
+        self.app.world.send_event(MouseButtonInput {
+            button,
+            state,
+            window: Entity::PLACEHOLDER,
+        });
+    }
+
+    /// the player and its transform, if the player has been spawned.
+    pub fn player(&mut self) -> Option<(&Transform, &Player)> {
+        self.app
+            .world
+            .query::<(&Transform, &Player)>()
+            .get_single(&self.app.world)
+            .ok()
+    }
+
+    /// the player, to change it directly, if the player has been spawned.
+    pub fn player_mut(&mut self) -> Option<Mut<'_, Player>> {
+        self.app
+            .world
+            .query::<&mut Player>()
+            .get_single_mut(&mut self.app.world)
+            .ok()
+    }
+
+    /// the turret, to change it directly, if the level has exactly one.
+    pub fn turret_mut(&mut self) -> Option<Mut<'_, Turret>> {
+        self.app
+            .world
+            .query::<&mut Turret>()
+            .get_single_mut(&mut self.app.world)
+            .ok()
+    }
+}
@@ -0,0 +1,160 @@
+use bevy::prelude::*;
+
+use crate::{
+    fixed_step::Interpolated,
+    level::{Level, LevelConfig, OnDeath, RestartLevel},
+    player::Player,
+    GameSet,
+};
+
+/// how long the player can't be hurt for after getting hit or respawning, in seconds.
+pub const INVULNERABILITY: f32 = 1.0;
+/// how long the player stays dead for before it respawns, in seconds.
+pub const RESPAWN_DELAY: f32 = 1.5;
+
+/// the health of the player, its death, and respawning it.
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlayerDamaged>()
+            .add_event::<PlayerDied>()
+            .configure_sets(
+                FixedUpdate,
+                GameSet::Health
+                    .after(GameSet::Player)
+                    .after(GameSet::Turrets)
+                    .after(GameSet::Bullets),
+            )
+            .add_systems(
+                FixedUpdate,
+                (count_down_invulnerability, kill_player, respawn_player)
+                    .chain()
+                    .in_set(GameSet::Health),
+            )
+            .add_systems(Update, blink_player.in_set(GameSet::Player));
+    }
+}
+
+/// the player lost `amount` health, and has `health` left.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct PlayerDamaged {
+    pub amount: f32,
+    pub health: f32,
+}
+
+/// the player ran out of health at `pos`.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct PlayerDied {
+    pub pos: Vec2,
+}
+
+/// the player is dead, and doesn't move or get hit until it respawns.
+#[derive(Component, Debug)]
+pub struct Dead {
+    /// seconds left until the player respawns.
+    pub respawn_in: f32,
+}
+
+/// hurt the player, unless it was hurt too recently or is already out of health.
+/// returns whether the player was hurt.
+pub fn damage_player(
+    player: &mut Player,
+    amount: f32,
+    damaged: &mut EventWriter<PlayerDamaged>,
+) -> bool {
+    if player.invulnerable > 0.0 || player.health <= 0.0 {
+        return false;
+    }
+    player.health -= amount;
+    player.invulnerable = INVULNERABILITY;
+    damaged.send(PlayerDamaged {
+        amount,
+        health: player.health,
+    });
+    true
+}
+
+fn count_down_invulnerability(time: Res<Time<Fixed>>, mut player: Query<&mut Player>) {
+    for mut player in &mut player {
+        player.invulnerable = (player.invulnerable - time.delta_seconds()).max(0.0);
+    }
+}
+
+/// kill the player once it's out of health, which also ends bullet time.
+fn kill_player(
+    mut commands: Commands,
+    mut time: ResMut<Time<Virtual>>,
+    mut died: EventWriter<PlayerDied>,
+    mut player: Query<(Entity, &Transform, &mut Player), Without<Dead>>,
+) {
+    let Ok((entity, transform, mut player)) = player.get_single_mut() else {
+        return;
+    };
+    if player.health > 0.0 {
+        return;
+    }
+    if player.bullet_time {
+        player.bullet_time = false;
+        time.set_relative_speed(1.0);
+    }
+    player.bounce = None;
+    commands.entity(entity).insert(Dead {
+        respawn_in: RESPAWN_DELAY,
+    });
+    died.send(PlayerDied {
+        pos: transform.translation.truncate(),
+    });
+}
+
+/// once the player has been dead for long enough, bring it back at the spawn of the level
+/// with full health, or restart the level, depending on `LevelConfig::on_death`.
+fn respawn_player(
+    mut commands: Commands,
+    time: Res<Time<Fixed>>,
+    config: Res<LevelConfig>,
+    level: Res<Level>,
+    mut restart: EventWriter<RestartLevel>,
+    mut player: Query<(
+        Entity,
+        &mut Transform,
+        &mut Interpolated,
+        &mut Player,
+        &mut Dead,
+    )>,
+) {
+    let Ok((entity, mut transform, mut interpolated, mut player, mut dead)) =
+        player.get_single_mut()
+    else {
+        return;
+    };
+    dead.respawn_in -= time.delta_seconds();
+    if dead.respawn_in > 0.0 {
+        return;
+    }
+    match config.on_death {
+        OnDeath::Respawn => {
+            *player = Player {
+                invulnerable: INVULNERABILITY,
+                ..Player::new()
+            };
+            transform.translation = level.player_spawn().extend(transform.translation.z);
+            interpolated.teleport(*transform);
+            commands.entity(entity).remove::<Dead>();
+        }
+        // the level spawns a new player.
+        OnDeath::Restart => restart.send(RestartLevel),
+    }
+}
+
+/// hide the player while it's dead, and make it blink while it can't be hurt.
+fn blink_player(mut player: Query<(&Player, Has<Dead>, &mut Visibility)>) {
+    for (player, dead, mut visibility) in &mut player {
+        let hidden = dead || (player.invulnerable * 10.0) as i32 % 2 == 1;
+        *visibility = if hidden {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+    }
+}
@@ -0,0 +1,55 @@
+use bevy::prelude::*;
+
+use crate::{health::Dead, player::Player};
+
+/// text in the corner of the screen showing the health of the player.
+/// this needs bevy's ui and text plugins, so it's added by `main.rs` instead of `GamePlugin`.
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_hud)
+            .add_systems(Update, update_hud);
+    }
+}
+
+#[derive(Component)]
+pub struct HealthText;
+
+fn setup_hud(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 24.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        HealthText,
+    ));
+}
+
+/// shows the health of the player, in red right after it got hurt.
+fn update_hud(player: Query<(&Player, Has<Dead>)>, mut text: Query<&mut Text, With<HealthText>>) {
+    let (Ok((player, dead)), Ok(mut text)) = (player.get_single(), text.get_single_mut()) else {
+        return;
+    };
+    let section = &mut text.sections[0];
+    section.value = if dead {
+        "dead".to_string()
+    } else {
+        format!("health {:.1} / {:.1}", player.health, player.max_health)
+    };
+    section.style.color = if player.invulnerable > 0.0 {
+        Color::RED
+    } else {
+        Color::WHITE
+    };
+}
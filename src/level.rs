@@ -0,0 +1,565 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context};
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+use bevy_rapier2d::{
+    plugin::systems::{init_colliders, sync_removals},
+    prelude::*,
+};
+use noise::{Fbm, MultiFractal, NoiseFn, Simplex};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bullet::Bullet,
+    chunks::{setup_chunks, stream_chunks, ChunkConfig},
+    level_gen::{
+        dungeon::{generate_dungeon, Bounds, DungeonParams, RasterParams},
+        file::{load_level, parse_ascii, save_level},
+        contours::Contour,
+        indexed_mesh::IndexedMesh,
+        markers::{LevelData, Marker, MarkerKind},
+        matrix::Matrix,
+        point::Point,
+        regions::{connect_regions, find_spawn, remove_pockets, Regions},
+        tiles::Tiles,
+    },
+    mesh::{contours_to_collider, indexed_to_mesh},
+    player::{spawn_player, Player},
+    terrain::{
+        apply_terrain_edits, rebuild_terrain, spawn_terrain_regions, sync_terrain_colliders,
+        Terrain, TerrainEdit, REGION_SIZE,
+    },
+    turret::{spawn_turret, Turret},
+    Environment, GameSet,
+};
+
+/// player spawn (in node coordinates) used when the level doesn't have any open space.
+const DEFAULT_SPAWN: Vec2 = Vec2::new(0.5, 0.5);
+
+/// builds the level at startup from `config`, and keeps the terrain
+/// (or the chunks of an endless level) up to date while the game runs.
+pub struct LevelPlugin {
+    pub config: LevelConfig,
+    /// the level built from `config` (see `LevelConfig::build_level`), None for endless levels.
+    pub level: Option<LevelData>,
+}
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.config.clone())
+            .init_resource::<ChunkConfig>()
+            .add_event::<TerrainEdit>()
+            .add_event::<RestartLevel>()
+            .configure_sets(Update, GameSet::Level.after(GameSet::Player))
+            .configure_sets(FixedUpdate, GameSet::Terrain.before(GameSet::Player))
+            .add_systems(
+                Startup,
+                (
+                    setup_env.run_if(resource_exists::<StartupLevel>()),
+                    setup_chunks.run_if(endless_level),
+                    apply_deferred,
+                    spawn_level_entities,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    stream_chunks.run_if(endless_level),
+                    (apply_terrain_edits, rebuild_terrain)
+                        .chain()
+                        .run_if(resource_exists::<Terrain>()),
+                )
+                    .in_set(GameSet::Level),
+            )
+            .add_systems(
+                FixedUpdate,
+                // rapier adds and removes the colliders of rebuilt regions,
+                // so the player collides with them in the very next step.
+                (
+                    rebuild_terrain,
+                    apply_deferred,
+                    init_colliders,
+                    sync_removals,
+                    apply_deferred,
+                    sync_terrain_colliders,
+                )
+                    .chain()
+                    .run_if(terrain_changed)
+                    .in_set(GameSet::Terrain),
+            )
+            .add_systems(FixedUpdate, restart_level.after(GameSet::Health));
+        if let Some(level) = &self.level {
+            app.insert_resource(StartupLevel(level.clone()));
+        }
+    }
+}
+
+/// where the density matrix of the level comes from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LevelSource {
+    /// fractal noise, configured by the noise parameters of `LevelConfig`.
+    Noise,
+    /// rooms and corridors from `generate_dungeon`.
+    Dungeon,
+    /// a hand authored ascii or png level file.
+    File(PathBuf),
+    /// the contents of an ascii level file (see `parse_ascii`), useful for tests.
+    Ascii(String),
+}
+
+/// what to do with open areas that can't be reached from the largest open area of the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PocketHandling {
+    /// leave the level as it is.
+    Keep,
+    /// fill pockets smaller than `LevelConfig::min_pocket_size` with walls.
+    Remove,
+    /// remove small pockets, then carve tunnels from the remaining ones to the largest area.
+    Connect,
+}
+
+/// what happens once the player has been dead for a moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OnDeath {
+    /// bring the player back at the spawn, leaving the rest of the level as it is.
+    #[default]
+    Respawn,
+    /// put the whole level back the way it was at startup (see `RestartLevel`).
+    Restart,
+}
+
+/// parameters used to build the level at startup.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct LevelConfig {
+    pub source: LevelSource,
+    /// if set, the level is written to this path once it's built.
+    pub save_path: Option<PathBuf>,
+    pub seed: u32,
+    /// number of density nodes along each axis.
+    pub dimensions: [usize; 2],
+    pub octaves: usize,
+    pub frequency: f64,
+    pub lacunarity: f64,
+    /// noise values below the threshold become walls, the rest is open space.
+    pub threshold: f64,
+    /// distance in world units between two neighbouring density nodes.
+    pub node_spacing: f64,
+    pub pockets: PocketHandling,
+    /// pockets with fewer nodes than this are removed, unless pockets are kept.
+    pub min_pocket_size: usize,
+    /// stream an endless noise level in chunks around the camera instead of
+    /// building a single level of the configured dimensions.
+    pub endless: bool,
+    /// kept with the level, so that replays of runs where the player died play out the same.
+    #[serde(default)]
+    pub on_death: OnDeath,
+}
+
+impl Default for LevelConfig {
+    fn default() -> Self {
+        Self {
+            source: LevelSource::Noise,
+            save_path: None,
+            seed: 0,
+            dimensions: [100, 100],
+            octaves: Fbm::<Simplex>::DEFAULT_OCTAVE_COUNT,
+            frequency: Fbm::<Simplex>::DEFAULT_FREQUENCY,
+            lacunarity: Fbm::<Simplex>::DEFAULT_LACUNARITY,
+            threshold: 0.0,
+            node_spacing: 20.0,
+            pockets: PocketHandling::Keep,
+            min_pocket_size: 20,
+            endless: false,
+            on_death: OnDeath::Respawn,
+        }
+    }
+}
+
+impl LevelConfig {
+    /// build a config from command line arguments of the form `--name value`.
+    /// any parameter that isn't provided keeps its default value, except for
+    /// the seed, which is picked at random so that every run gets a different map.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut config = Self {
+            seed: rand::random(),
+            ..default()
+        };
+        while let Some(arg) = args.next() {
+            if arg == "--endless" {
+                config.endless = true;
+                continue;
+            }
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("missing value for argument {}", arg))
+            };
+            match arg.as_str() {
+                "--level" => config.source = LevelSource::File(value()?.into()),
+                "--generator" => {
+                    config.source = match value()?.as_str() {
+                        "noise" => LevelSource::Noise,
+                        "dungeon" => LevelSource::Dungeon,
+                        other => return Err(anyhow!("unknown generator {}", other)),
+                    }
+                }
+                "--save-level" => config.save_path = Some(value()?.into()),
+                "--seed" => config.seed = value()?.parse().context("invalid --seed")?,
+                "--width" => config.dimensions[0] = value()?.parse().context("invalid --width")?,
+                "--height" => config.dimensions[1] = value()?.parse().context("invalid --height")?,
+                "--octaves" => config.octaves = value()?.parse().context("invalid --octaves")?,
+                "--frequency" => config.frequency = value()?.parse().context("invalid --frequency")?,
+                "--lacunarity" => {
+                    config.lacunarity = value()?.parse().context("invalid --lacunarity")?
+                }
+                "--threshold" => config.threshold = value()?.parse().context("invalid --threshold")?,
+                "--node-spacing" => {
+                    config.node_spacing = value()?.parse().context("invalid --node-spacing")?
+                }
+                "--pockets" => {
+                    config.pockets = match value()?.as_str() {
+                        "keep" => PocketHandling::Keep,
+                        "remove" => PocketHandling::Remove,
+                        "connect" => PocketHandling::Connect,
+                        other => return Err(anyhow!("unknown pocket handling {}", other)),
+                    }
+                }
+                "--on-death" => {
+                    config.on_death = match value()?.as_str() {
+                        "respawn" => OnDeath::Respawn,
+                        "restart" => OnDeath::Restart,
+                        other => return Err(anyhow!("unknown death handling {}", other)),
+                    }
+                }
+                "--min-pocket-size" => {
+                    config.min_pocket_size = value()?.parse().context("invalid --min-pocket-size")?
+                }
+                _ => return Err(anyhow!("unknown argument {}", arg)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// build the level and save it to `save_path` if one is set. endless levels
+    /// are built chunk by chunk while the game runs, so there's nothing to build for them.
+    pub fn build_level(&self) -> anyhow::Result<Option<LevelData>> {
+        if self.endless {
+            return Ok(None);
+        }
+        let level = self.level_data()?;
+        if let Some(path) = &self.save_path {
+            save_level(&level, path)?;
+        }
+        Ok(Some(level))
+    }
+
+    /// build the level from the configured source.
+    pub fn level_data(&self) -> anyhow::Result<LevelData> {
+        match &self.source {
+            LevelSource::Noise => Ok(LevelData::new(self.noise_densities(), vec![])),
+            LevelSource::Dungeon => Ok(self.dungeon_level()),
+            LevelSource::File(path) => load_level(path),
+            LevelSource::Ascii(text) => parse_ascii(text),
+        }
+    }
+
+    /// generate a dungeon that exactly fills the configured dimensions once rasterized.
+    pub fn dungeon_level(&self) -> LevelData {
+        let raster = RasterParams::default();
+        let border = raster.wall_thickness * 2;
+        let extents = UVec2::new(
+            (self.dimensions[0] as u32).saturating_sub(border),
+            (self.dimensions[1] as u32).saturating_sub(border),
+        );
+        let dungeon = generate_dungeon(
+            self.seed as u64,
+            Bounds::new(IVec2::ZERO, extents),
+            &DungeonParams::default(),
+        );
+        LevelData::new(dungeon.rasterize(&raster), dungeon.markers(&raster))
+    }
+
+    /// the fractal noise function described by this config.
+    pub fn noise(&self) -> Fbm<Simplex> {
+        Fbm::<Simplex>::new(self.seed)
+            .set_octaves(self.octaves)
+            .set_frequency(self.frequency)
+            .set_lacunarity(self.lacunarity)
+    }
+
+    /// sample fractal noise into a density matrix using the parameters of this config.
+    pub fn noise_densities(&self) -> Matrix<i8, 2> {
+        self.noise_densities_at(&self.noise(), IVec2::ZERO, self.dimensions)
+    }
+
+    /// sample `fbm` into a density matrix with dimensions `dim`, where node (0, 0)
+    /// of the matrix is node `origin` of the world. the noise is sampled in world space
+    /// (scaled by the configured dimensions), so matrices of neighboring areas line up.
+    pub fn noise_densities_at(
+        &self,
+        fbm: &Fbm<Simplex>,
+        origin: IVec2,
+        dim: [usize; 2],
+    ) -> Matrix<i8, 2> {
+        let scale: Point<usize, 2> = self.dimensions.into();
+        let scale = Point::new([scale[0] as f64, scale[1] as f64]);
+        let mut matrix = Matrix::new(dim);
+        for y in 0..dim[1] {
+            for x in 0..dim[0] {
+                let node = Point::new([(origin.x + x as i32) as f64, (origin.y + y as i32) as f64]);
+                let pt = node / scale;
+                let z = fbm.get(pt.v);
+                if z < self.threshold {
+                    matrix.set([x, y], -1);
+                } else {
+                    matrix.set([x, y], 1);
+                }
+            }
+        }
+        matrix
+    }
+}
+
+/// the level data that `setup_env` builds the terrain from. it's built before the app
+/// starts, so that problems with the level are reported as errors instead of panics.
+#[derive(Resource)]
+pub struct StartupLevel(pub LevelData);
+
+/// the level that was built at startup.
+#[derive(Resource, Debug)]
+pub struct Level {
+    pub dimensions: [usize; 2],
+    pub node_spacing: f64,
+    pub markers: Vec<Marker>,
+}
+
+impl Level {
+    /// converts a position in node coordinates into world coordinates.
+    /// the density matrix is rendered with the y axis pointing down,
+    /// so node y coordinates are flipped.
+    pub fn node_to_world(&self, pos: Vec2) -> Vec2 {
+        Vec2::new(pos.x, -pos.y) * self.node_spacing as f32
+    }
+
+    /// the area covered by the terrain in world space, including the solid border around
+    /// the tilemap. None for endless levels, which don't have any bounds.
+    pub fn bounds(&self) -> Option<Rect> {
+        if self.dimensions.contains(&0) {
+            return None;
+        }
+        let far = Vec2::new(self.dimensions[0] as f32, self.dimensions[1] as f32) + Vec2::ONE;
+        Some(Rect::from_corners(
+            self.node_to_world(Vec2::splat(-2.0)),
+            self.node_to_world(far),
+        ))
+    }
+
+    /// world position the player should spawn at.
+    pub fn player_spawn(&self) -> Vec2 {
+        let spawn = self
+            .markers
+            .iter()
+            .find(|marker| marker.kind == MarkerKind::PlayerSpawn)
+            .map_or(DEFAULT_SPAWN, |marker| marker.pos);
+        self.node_to_world(spawn)
+    }
+}
+
+/// the goal of the level.
+#[derive(Component, Debug)]
+pub struct Exit {
+    pub radius: f32,
+}
+
+/// run condition that is true when the level is streamed in chunks (see `chunks`).
+pub fn endless_level(config: Res<LevelConfig>) -> bool {
+    config.endless
+}
+
+/// run condition that is true when the terrain was edited or reset since it was last rebuilt.
+fn terrain_changed(terrain: Option<Res<Terrain>>) -> bool {
+    terrain.is_some_and(|terrain| terrain.is_dirty())
+}
+
+pub fn setup_env(
+    mut commands: Commands,
+    config: Res<LevelConfig>,
+    level: Res<StartupLevel>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let LevelData {
+        densities,
+        mut markers,
+    } = level.0.clone();
+    let mut tiles = Tiles::new(densities, config.node_spacing);
+    process_pockets(&config, &mut tiles);
+    place_player_spawn(&tiles, &mut markers);
+    commands.insert_resource(Level {
+        dimensions: tiles.dimension(),
+        node_spacing: config.node_spacing,
+        markers,
+    });
+    let mut terrain = Terrain::new(tiles, REGION_SIZE);
+    spawn_terrain_regions(&mut commands, &mut meshes, &mut materials, &mut terrain);
+    commands.insert_resource(terrain);
+}
+
+/// spawn a piece of the environment from the mesh and contours returned by marching squares.
+pub fn spawn_terrain(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    mesh: IndexedMesh,
+    contours: Vec<Contour>,
+    transform: Transform,
+) -> Entity {
+    let mut entity = commands.spawn((
+        RigidBody::Fixed,
+        Environment,
+        Restitution {
+            coefficient: 1.0,
+            combine_rule: CoefficientCombineRule::Max,
+        },
+        Friction::coefficient(1.0),
+        MaterialMesh2dBundle {
+            mesh: meshes.add(indexed_to_mesh(mesh)).into(),
+            material: materials.add(ColorMaterial::from(Color::BLACK)),
+            transform,
+            ..default()
+        },
+    ));
+    if let Some(collider) = contours_to_collider(&contours) {
+        entity.insert(collider);
+    }
+    entity.id()
+}
+
+fn process_pockets(config: &LevelConfig, tiles: &mut Tiles) {
+    if config.pockets == PocketHandling::Keep {
+        return;
+    }
+    let regions = Regions::new(tiles);
+    let regions = if remove_pockets(tiles, &regions, config.min_pocket_size) {
+        Regions::new(tiles)
+    } else {
+        regions
+    };
+    if config.pockets == PocketHandling::Connect {
+        connect_regions(tiles, &regions);
+    }
+}
+
+/// makes sure the player spawns in open space that is connected to the rest of the level.
+/// spawn markers that are inside a wall or in a pocket separate from the largest open area
+/// are replaced with the most open spot of the largest area.
+fn place_player_spawn(tiles: &Tiles, markers: &mut Vec<Marker>) {
+    let regions = Regions::new(tiles);
+    let spawn = markers
+        .iter()
+        .position(|marker| marker.kind == MarkerKind::PlayerSpawn);
+    if let Some(i) = spawn {
+        if regions.in_largest(markers[i].pos) {
+            return;
+        }
+        warn!("player spawn at {} isn't reachable, moving it", markers[i].pos);
+        markers.remove(i);
+    }
+    if let Some(pos) = find_spawn(tiles, &regions) {
+        markers.insert(
+            0,
+            Marker {
+                pos,
+                kind: MarkerKind::PlayerSpawn,
+            },
+        );
+    }
+}
+
+/// spawns the player and the entities of the markers once the level has been built.
+pub fn spawn_level_entities(
+    mut commands: Commands,
+    level: Res<Level>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    populate_level(&mut commands, &mut meshes, &mut materials, &level);
+}
+
+/// sent to put the level back the way it was at startup. edits to the terrain are undone,
+/// and the player, turrets and exits are spawned again, while bullets are removed.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct RestartLevel;
+
+/// the entities that are removed when the level restarts.
+type LevelContents = Or<(With<Player>, With<Turret>, With<Bullet>, With<Exit>)>;
+
+pub fn restart_level(
+    mut commands: Commands,
+    mut restarts: EventReader<RestartLevel>,
+    level: Res<Level>,
+    terrain: Option<ResMut<Terrain>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    entities: Query<Entity, LevelContents>,
+) {
+    if restarts.read().count() == 0 {
+        return;
+    }
+    for entity in &entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    if let Some(mut terrain) = terrain {
+        terrain.reset();
+    }
+    populate_level(&mut commands, &mut meshes, &mut materials, &level);
+}
+
+/// turns the markers of the level into entities. the player is always spawned,
+/// at the origin of the level if there's no spawn marker.
+fn populate_level(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    level: &Level,
+) {
+    spawn_player(commands, meshes, materials, level.player_spawn());
+    for marker in &level.markers {
+        let pos = level.node_to_world(marker.pos);
+        match &marker.kind {
+            MarkerKind::PlayerSpawn => {}
+            MarkerKind::Turret {
+                fire_rate,
+                rot_speed,
+                accuracy,
+                lead,
+                archetype,
+            } => {
+                spawn_turret(
+                    commands,
+                    meshes,
+                    materials,
+                    pos,
+                    Turret {
+                        accuracy: *accuracy,
+                        lead: *lead,
+                        archetype: archetype.clone(),
+                        ..Turret::new(*fire_rate, *rot_speed)
+                    },
+                );
+            }
+            MarkerKind::Exit => {
+                let exit = Exit { radius: 15.0 };
+                commands.spawn((
+                    MaterialMesh2dBundle {
+                        mesh: meshes.add(shape::Circle::new(exit.radius).into()).into(),
+                        material: materials.add(ColorMaterial::from(Color::GREEN)),
+                        transform: Transform::from_translation(pos.extend(0.5)),
+                        ..default()
+                    },
+                    exit,
+                ));
+            }
+        }
+    }
+}
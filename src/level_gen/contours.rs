@@ -0,0 +1,116 @@
+use bevy::{
+    prelude::Vec2,
+    utils::{HashMap, HashSet},
+};
+
+use super::indexed_mesh::IndexedMesh;
+
+/// ordered outline of a wall. consecutive points are connected,
+/// and if the contour is closed the last point is also connected to the first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contour {
+    pub points: Vec<Vec2>,
+    pub closed: bool,
+}
+
+impl Contour {
+    /// iterate over every segment of the contour.
+    pub fn segments(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        let wrap = self.closed && self.points.len() > 2;
+        self.points
+            .windows(2)
+            .map(|w| (w[0], w[1]))
+            .chain(wrap.then(|| (self.points[self.points.len() - 1], self.points[0])))
+    }
+}
+
+/// finds the outline of a mesh by chaining together its boundary edges
+/// (edges that belong to only one triangle). only boundary edges whose midpoint
+/// passes `keep` are used, so a mesh built with a margin around some range can be
+/// clipped back to that range. contours that get clipped come out as open polylines.
+///
+/// edges keep the direction they have in their triangle, so for consistently wound meshes
+/// all contours go around the solid area in the same direction.
+pub fn extract_contours(mesh: &IndexedMesh, keep: impl Fn(Vec2) -> bool) -> Vec<Contour> {
+    let mut edges: HashSet<(u32, u32)> = HashSet::new();
+    for [a, b, c] in mesh.triangles() {
+        for (from, to) in [(a, b), (b, c), (c, a)] {
+            // an edge shared by two consistently wound triangles shows up once in each direction.
+            if !edges.remove(&(to, from)) {
+                edges.insert((from, to));
+            }
+        }
+    }
+
+    let point = |i: u32| mesh.vertices[i as usize].truncate();
+    let mut next: HashMap<u32, Vec<u32>> = HashMap::new();
+    let mut has_incoming: HashSet<u32> = HashSet::new();
+    let mut kept: Vec<(u32, u32)> = edges
+        .into_iter()
+        .filter(|(from, to)| keep((point(*from) + point(*to)) / 2.0))
+        .collect();
+    // hash set iteration order isn't stable, sort so the same mesh always gives the same contours.
+    kept.sort_unstable();
+    for (from, to) in kept {
+        next.entry(from).or_default().push(to);
+        has_incoming.insert(to);
+    }
+
+    // start with the open chains, so that they are followed from their first point.
+    let mut starts: Vec<u32> = next
+        .keys()
+        .copied()
+        .filter(|v| !has_incoming.contains(v))
+        .collect();
+    starts.sort_unstable();
+    let mut rest: Vec<u32> = next.keys().copied().filter(|v| has_incoming.contains(v)).collect();
+    rest.sort_unstable();
+    starts.extend(rest);
+
+    let mut contours = vec![];
+    for start in starts {
+        while next.get(&start).is_some_and(|to| !to.is_empty()) {
+            let mut points = vec![point(start)];
+            let mut current = start;
+            let mut closed = false;
+            while let Some(to) = next.get_mut(&current).and_then(|to| to.pop()) {
+                if to == start {
+                    closed = true;
+                    break;
+                }
+                points.push(point(to));
+                current = to;
+            }
+            contours.push(Contour { points, closed });
+        }
+    }
+    contours
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level_gen::{marching_squares::marching_squares_in, matrix::Matrix, tiles::Tiles};
+
+    #[test]
+    fn filled_block_has_one_outline_and_no_interior_edges() {
+        let mut matrix = Matrix::new([9, 9]);
+        for y in 0..9 {
+            for x in 0..9 {
+                let solid = (3..=5).contains(&x) && (3..=5).contains(&y);
+                matrix.set([x, y], if solid { -1 } else { 1 });
+            }
+        }
+        // leave out the edge of the level, which is solid too.
+        let (_, contours) =
+            marching_squares_in(&Tiles::new(matrix, 1.0), [1, 1].into(), [8, 8].into());
+        assert_eq!(contours.len(), 1, "{:?}", contours);
+        assert!(contours[0].closed);
+        // the density crosses zero half way between the block and the open nodes around it.
+        let on_edge = |v: f32| (v - 2.5).abs() < 1e-4 || (v - 5.5).abs() < 1e-4;
+        for p in &contours[0].points {
+            let (x, y) = (p.x, -p.y);
+            assert!((2.5..=5.5).contains(&x) && (2.5..=5.5).contains(&y), "{}", p);
+            assert!(on_edge(x) || on_edge(y), "interior point {}", p);
+        }
+    }
+}
@@ -1,19 +1,178 @@
 use bevy::prelude::{IVec2, UVec2};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-struct Bounds {
-    pos: IVec2,
-    extents: UVec2,
+use super::{
+    markers::{Marker, MarkerKind},
+    matrix::Matrix,
+};
+
+/// density of open space in a rasterized dungeon. marching squares
+/// treats positive densities as empty and zero or negative densities as solid.
+const OPEN_DENSITY: i8 = 1;
+/// density of walls in a rasterized dungeon.
+const WALL_DENSITY: i8 = -1;
+
+/// axis aligned rectangle of cells, `pos` is the minimum corner
+/// and `extents` is the size of the rectangle along each axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub pos: IVec2,
+    pub extents: UVec2,
 }
 
-struct Room {
-    bounds: Bounds,
+impl Bounds {
+    pub fn new(pos: IVec2, extents: UVec2) -> Self {
+        Self { pos, extents }
+    }
+
+    /// exclusive maximum corner of the bounds.
+    pub fn max(&self) -> IVec2 {
+        self.pos + self.extents.as_ivec2()
+    }
+
+    /// the cell closest to the middle of the bounds.
+    pub fn center(&self) -> IVec2 {
+        self.pos + self.extents.as_ivec2() / 2
+    }
+
+    pub fn contains(&self, pt: IVec2) -> bool {
+        pt.cmpge(self.pos).all() && pt.cmplt(self.max()).all()
+    }
+
+    /// returns true if the two bounds overlap, or if they are closer
+    /// than `padding` cells to each other.
+    pub fn intersects(&self, other: &Bounds, padding: i32) -> bool {
+        let padding = IVec2::splat(padding);
+        (self.pos - padding).cmplt(other.max()).all() && other.pos.cmplt(self.max() + padding).all()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Room {
+    pub bounds: Bounds,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dungeon {
+    pub bounds: Bounds,
+    pub rooms: Vec<Room>,
+    pub corridors: Vec<Corridor>,
 }
 
-struct Dungeon {
-    bounds: Bounds,
+/// controls how a `Dungeon` is turned into a density matrix.
+#[derive(Debug, Clone)]
+pub struct RasterParams {
+    /// width of corridors in cells.
+    pub corridor_width: u32,
+    /// number of solid cells surrounding the dungeon bounds, so rooms at
+    /// the edge of the dungeon are still enclosed by a wall of this thickness.
+    pub wall_thickness: u32,
+}
+
+impl Default for RasterParams {
+    fn default() -> Self {
+        Self {
+            corridor_width: 3,
+            wall_thickness: 2,
+        }
+    }
 }
 
-enum Direction {
+impl Dungeon {
+    /// rasterize the dungeon into a density matrix that can be passed to `Tiles::new`.
+    /// rooms and corridors get a positive (open) density, everything else
+    /// gets a negative (wall) density, so marching squares produces smooth walls around them.
+    pub fn rasterize(&self, params: &RasterParams) -> Matrix<i8, 2> {
+        let border = params.wall_thickness as usize * 2;
+        let dim = [
+            self.bounds.extents.x as usize + border,
+            self.bounds.extents.y as usize + border,
+        ];
+        let mut matrix = Matrix::new(dim);
+        for y in 0..dim[1] {
+            for x in 0..dim[0] {
+                matrix.set([x, y], WALL_DENSITY);
+            }
+        }
+
+        let mut carve = |cell: IVec2| {
+            let node = self.cell_to_node(cell, params);
+            if node.x >= 0 && node.y >= 0 && (node.x as usize) < dim[0] && (node.y as usize) < dim[1] {
+                matrix.set([node.x as usize, node.y as usize], OPEN_DENSITY);
+            }
+        };
+
+        for room in &self.rooms {
+            let min = room.bounds.pos;
+            let max = room.bounds.max();
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    carve(IVec2::new(x, y));
+                }
+            }
+        }
+
+        let width = params.corridor_width.max(1) as i32;
+        let (lo, hi) = (-(width - 1) / 2, width / 2);
+        for corridor in &self.corridors {
+            for cell in corridor.cells() {
+                for dy in lo..=hi {
+                    for dx in lo..=hi {
+                        carve(cell + IVec2::new(dx, dy));
+                    }
+                }
+            }
+        }
+        matrix
+    }
+
+    /// place the player spawn in the first room, the exit in the room furthest away
+    /// from it, and a turret in the middle of every other room.
+    /// marker positions are nodes of the matrix returned by `rasterize`.
+    pub fn markers(&self, params: &RasterParams) -> Vec<Marker> {
+        let Some(spawn_room) = self.rooms.first() else {
+            return vec![];
+        };
+        let spawn = spawn_room.bounds.center();
+        let exit_index = self
+            .rooms
+            .iter()
+            .enumerate()
+            .skip(1)
+            .max_by_key(|(_, room)| {
+                let diff = room.bounds.center() - spawn;
+                diff.x * diff.x + diff.y * diff.y
+            })
+            .map(|(i, _)| i);
+
+        let to_pos = |cell: IVec2| self.cell_to_node(cell, params).as_vec2();
+        let mut markers = vec![Marker {
+            pos: to_pos(spawn),
+            kind: MarkerKind::PlayerSpawn,
+        }];
+        for (i, room) in self.rooms.iter().enumerate().skip(1) {
+            let kind = if Some(i) == exit_index {
+                MarkerKind::Exit
+            } else {
+                MarkerKind::default_turret()
+            };
+            markers.push(Marker {
+                pos: to_pos(room.bounds.center()),
+                kind,
+            });
+        }
+        markers
+    }
+
+    /// converts a cell in dungeon space into a node index
+    /// of the matrix returned by `rasterize`.
+    pub fn cell_to_node(&self, cell: IVec2, params: &RasterParams) -> IVec2 {
+        cell - self.bounds.pos + IVec2::splat(params.wall_thickness as i32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
     Up,
     Down,
     Left,
@@ -43,14 +202,270 @@ impl Into<Direction> for IVec2 {
     }
 }
 
-struct Corridor {
-    pos: IVec2,
-    len: u32,
-    dir: Direction,
+/// straight line of cells starting at `pos` and
+/// extending `len` cells in the direction `dir` (including `pos`).
+#[derive(Debug, Clone, Copy)]
+pub struct Corridor {
+    pub pos: IVec2,
+    pub len: u32,
+    pub dir: Direction,
+}
+
+impl Corridor {
+    /// iterate over every cell covered by this corridor.
+    pub fn cells(&self) -> impl Iterator<Item = IVec2> {
+        let pos = self.pos;
+        let dir: IVec2 = self.dir.into();
+        (0..self.len as i32).map(move |i| pos + dir * i)
+    }
+}
+
+/// parameters that control the layout of a generated dungeon.
+#[derive(Debug, Clone)]
+pub struct DungeonParams {
+    /// number of times we try to place a room, rooms that
+    /// overlap previously placed rooms are discarded.
+    pub room_attempts: u32,
+    pub min_room_size: UVec2,
+    pub max_room_size: UVec2,
+    /// minimum number of empty cells between any two rooms.
+    pub room_padding: i32,
+}
+
+impl Default for DungeonParams {
+    fn default() -> Self {
+        Self {
+            room_attempts: 60,
+            min_room_size: UVec2::new(6, 6),
+            max_room_size: UVec2::new(16, 12),
+            room_padding: 3,
+        }
+    }
+}
+
+/// generates a dungeon inside of `bounds` using the provided seed.
+/// rooms never overlap, and every room is connected to every other room
+/// through a series of corridors. the same seed and params always produce the same dungeon.
+pub fn generate_dungeon(seed: u64, bounds: Bounds, params: &DungeonParams) -> Dungeon {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let rooms = place_rooms(&mut rng, &bounds, params);
+    let corridors = connect_rooms(&mut rng, &rooms);
+    Dungeon {
+        bounds,
+        rooms,
+        corridors,
+    }
 }
 
+fn place_rooms(rng: &mut StdRng, bounds: &Bounds, params: &DungeonParams) -> Vec<Room> {
+    let mut rooms: Vec<Room> = vec![];
+    for _ in 0..params.room_attempts {
+        let extents = UVec2::new(
+            rng.gen_range(params.min_room_size.x..=params.max_room_size.x),
+            rng.gen_range(params.min_room_size.y..=params.max_room_size.y),
+        );
+        // leave a one cell border so that rooms are always enclosed by walls.
+        let free = bounds.extents.as_ivec2() - extents.as_ivec2() - IVec2::splat(2);
+        if free.x < 0 || free.y < 0 {
+            continue;
+        }
+        let pos = bounds.pos
+            + IVec2::ONE
+            + IVec2::new(rng.gen_range(0..=free.x), rng.gen_range(0..=free.y));
+        let room = Room {
+            bounds: Bounds::new(pos, extents),
+        };
+        if rooms
+            .iter()
+            .all(|other| !other.bounds.intersects(&room.bounds, params.room_padding))
+        {
+            rooms.push(room);
+        }
+    }
+    rooms
+}
 
-fn generate_dungeon() {
+/// connects every room to the closest room that was placed before it.
+/// since every room is connected to an earlier one, all of the rooms end up
+/// in the same tree and can be reached from each other.
+fn connect_rooms(rng: &mut StdRng, rooms: &[Room]) -> Vec<Corridor> {
+    let mut corridors = vec![];
+    for (i, room) in rooms.iter().enumerate().skip(1) {
+        let from = room.bounds.center();
+        let to = rooms[..i]
+            .iter()
+            .map(|other| other.bounds.center())
+            .min_by_key(|center| {
+                let diff = (*center - from).abs();
+                diff.x + diff.y
+            })
+            .expect("there is always at least one earlier room");
+        corridors.extend(l_corridor(from, to, rng.gen_bool(0.5)));
+    }
+    corridors
+}
 
+/// creates an L shaped pair of corridors between `from` and `to`.
+/// if `horizontal_first` is set, the corridor travels along the x axis first.
+fn l_corridor(from: IVec2, to: IVec2, horizontal_first: bool) -> [Corridor; 2] {
+    let corner = if horizontal_first {
+        IVec2::new(to.x, from.y)
+    } else {
+        IVec2::new(from.x, to.y)
+    };
+    [straight_corridor(from, corner), straight_corridor(corner, to)]
 }
 
+fn straight_corridor(from: IVec2, to: IVec2) -> Corridor {
+    let diff = to - from;
+    let abs = diff.abs();
+    let dir = if diff.x > 0 {
+        Direction::Right
+    } else if diff.x < 0 {
+        Direction::Left
+    } else if diff.y < 0 {
+        Direction::Down
+    } else {
+        Direction::Up
+    };
+    Corridor {
+        pos: from,
+        len: (abs.x + abs.y) as u32 + 1,
+        dir,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn bounds() -> Bounds {
+        Bounds::new(IVec2::new(-40, -30), UVec2::new(80, 60))
+    }
+
+    #[test]
+    fn rooms_never_overlap() {
+        let params = DungeonParams::default();
+        for seed in 0..20 {
+            let dungeon = generate_dungeon(seed, bounds(), &params);
+            assert!(
+                dungeon.rooms.len() > 1,
+                "seed {} placed {} rooms",
+                seed,
+                dungeon.rooms.len()
+            );
+            for (i, room) in dungeon.rooms.iter().enumerate() {
+                assert!(
+                    bounds().contains(room.bounds.pos)
+                        && bounds().contains(room.bounds.max() - IVec2::ONE)
+                );
+                for other in &dungeon.rooms[i + 1..] {
+                    assert!(
+                        !room.bounds.intersects(&other.bounds, params.room_padding),
+                        "seed {}: {:?} overlaps {:?}",
+                        seed,
+                        room.bounds,
+                        other.bounds
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_room_is_reachable_through_corridors() {
+        for seed in 0..20 {
+            let dungeon = generate_dungeon(seed, bounds(), &DungeonParams::default());
+            let mut open: HashSet<IVec2> =
+                dungeon.corridors.iter().flat_map(|c| c.cells()).collect();
+            for room in &dungeon.rooms {
+                let (min, max) = (room.bounds.pos, room.bounds.max());
+                open.extend(
+                    (min.y..max.y).flat_map(|y| (min.x..max.x).map(move |x| IVec2::new(x, y))),
+                );
+            }
+
+            let start = dungeon.rooms[0].bounds.center();
+            let mut reached = HashSet::from([start]);
+            let mut stack = vec![start];
+            while let Some(cell) = stack.pop() {
+                for dir in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+                    let next = cell + dir;
+                    if open.contains(&next) && reached.insert(next) {
+                        stack.push(next);
+                    }
+                }
+            }
+            for room in &dungeon.rooms {
+                assert!(
+                    reached.contains(&room.bounds.center()),
+                    "seed {}: {:?} is unreachable",
+                    seed,
+                    room.bounds
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_dungeon() {
+        let params = DungeonParams::default();
+        let a = generate_dungeon(7, bounds(), &params);
+        let b = generate_dungeon(7, bounds(), &params);
+        let rooms = |d: &Dungeon| d.rooms.iter().map(|r| r.bounds).collect::<Vec<_>>();
+        assert_eq!(rooms(&a), rooms(&b));
+        assert_eq!(a.corridors.len(), b.corridors.len());
+    }
+
+    #[test]
+    fn rooms_and_corridors_are_rasterized_as_open_space() {
+        let params = RasterParams::default();
+        for seed in 0..20 {
+            let dungeon = generate_dungeon(seed, bounds(), &DungeonParams::default());
+            let matrix = dungeon.rasterize(&params);
+            let density = |cell: IVec2| {
+                let node = dungeon.cell_to_node(cell, &params);
+                matrix.get([node.x as usize, node.y as usize])
+            };
+            for room in &dungeon.rooms {
+                let (min, max) = (room.bounds.pos, room.bounds.max());
+                for y in min.y..max.y {
+                    for x in min.x..max.x {
+                        assert_eq!(density(IVec2::new(x, y)), OPEN_DENSITY, "seed {}", seed);
+                    }
+                }
+            }
+            for cell in dungeon.corridors.iter().flat_map(|c| c.cells()) {
+                assert_eq!(density(cell), OPEN_DENSITY, "seed {}: {}", seed, cell);
+            }
+        }
+    }
+
+    #[test]
+    fn rasterized_dungeons_are_enclosed_by_walls() {
+        let params = RasterParams::default();
+        let t = params.wall_thickness as usize;
+        for seed in 0..20 {
+            let matrix =
+                generate_dungeon(seed, bounds(), &DungeonParams::default()).rasterize(&params);
+            let [w, h] = matrix.dim();
+            assert_eq!([w, h], [80 + 2 * t, 60 + 2 * t]);
+            for y in 0..h {
+                for x in 0..w {
+                    if x < t || y < t || x >= w - t || y >= h - t {
+                        assert_eq!(
+                            matrix.get([x, y]),
+                            WALL_DENSITY,
+                            "seed {}: [{}, {}]",
+                            seed,
+                            x,
+                            y
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
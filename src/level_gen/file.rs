@@ -0,0 +1,404 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use bevy::prelude::Vec2;
+use image::{GrayImage, Luma};
+
+use super::{
+    markers::{
+        LevelData, Marker, MarkerKind, DEFAULT_TURRET_ACCURACY, DEFAULT_TURRET_FIRE_RATE,
+        DEFAULT_TURRET_ARCHETYPE, DEFAULT_TURRET_LEAD, DEFAULT_TURRET_ROT_SPEED,
+    },
+    matrix::Matrix,
+};
+
+/// ascii character for open space.
+const OPEN_CHAR: char = '.';
+/// ascii character for walls with smooth edges (negative density).
+const WALL_CHAR: char = '#';
+/// ascii character for walls with square corners (zero density).
+const SQUARE_WALL_CHAR: char = '+';
+/// ascii characters for open space containing a marker.
+const SPAWN_CHAR: char = 'P';
+const TURRET_CHAR: char = 'T';
+const EXIT_CHAR: char = 'E';
+
+/// load a level from a file. files ending in `.png` are read as grayscale
+/// images (see `load_png`), everything else is read as an ascii grid (see `parse_ascii`).
+pub fn load_level(path: &Path) -> anyhow::Result<LevelData> {
+    if is_png(path) {
+        load_png(path)
+    } else {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read level file {}", path.display()))?;
+        parse_ascii(&text).with_context(|| format!("failed to parse level file {}", path.display()))
+    }
+}
+
+/// write a level to a file, using the same format selection as `load_level`.
+pub fn save_level(level: &LevelData, path: &Path) -> anyhow::Result<()> {
+    if is_png(path) {
+        save_png(level, path)
+    } else {
+        fs::write(path, to_ascii(level))
+            .with_context(|| format!("failed to write level file {}", path.display()))
+    }
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// parse an ascii grid where every line is a row of the level, starting from the top.
+/// `.` is open space, `#` is a wall, and `+` is a wall with square corners.
+/// `P`, `T` and `E` are open spaces with a player spawn, turret, or exit marker on them.
+/// all rows must have the same length.
+///
+/// the grid can be followed by an empty line and a list of markers (see `parse_markers`),
+/// which is needed to give turrets anything other than the default parameters.
+pub fn parse_ascii(text: &str) -> anyhow::Result<LevelData> {
+    let mut lines = text.lines().map(|line| line.trim_end());
+    let rows: Vec<Vec<char>> = lines
+        .by_ref()
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.chars().collect())
+        .collect();
+    let width = rows.first().map_or(0, |row| row.len());
+    if width == 0 {
+        return Err(anyhow!("level is empty"));
+    }
+    let mut matrix = Matrix::new([width, rows.len()]);
+    let mut markers = vec![];
+    for (y, row) in rows.iter().enumerate() {
+        if row.len() != width {
+            return Err(anyhow!(
+                "row {} has length {}, expected {}",
+                y + 1,
+                row.len(),
+                width
+            ));
+        }
+        for (x, c) in row.iter().enumerate() {
+            let pos = Vec2::new(x as f32, y as f32);
+            let density = match *c {
+                OPEN_CHAR => 1,
+                WALL_CHAR => -1,
+                SQUARE_WALL_CHAR => 0,
+                SPAWN_CHAR => {
+                    markers.push(Marker { pos, kind: MarkerKind::PlayerSpawn });
+                    1
+                }
+                TURRET_CHAR => {
+                    markers.push(Marker { pos, kind: MarkerKind::default_turret() });
+                    1
+                }
+                EXIT_CHAR => {
+                    markers.push(Marker { pos, kind: MarkerKind::Exit });
+                    1
+                }
+                c => return Err(anyhow!("unknown character '{}' at row {}, column {}", c, y + 1, x + 1)),
+            };
+            matrix.set([x, y], density);
+        }
+    }
+    markers.extend(parse_markers(&lines.collect::<Vec<_>>().join("\n"))?);
+    Ok(LevelData::new(matrix, markers))
+}
+
+/// parse a list of markers, one per line, in node coordinates:
+/// ```text
+/// spawn <x> <y>
+/// turret <x> <y> [fire_rate] [rot_speed] [accuracy] [lead] [archetype]
+/// exit <x> <y>
+/// ```
+/// empty lines and lines starting with `//` are ignored. `rot_speed` can't be negative,
+/// and `accuracy` and `lead` are clamped between 0 and 1.
+pub fn parse_markers(text: &str) -> anyhow::Result<Vec<Marker>> {
+    let mut markers = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let parse_marker = || -> anyhow::Result<Marker> {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let num = |i: usize| -> anyhow::Result<f32> {
+                let word = words.get(i).ok_or_else(|| anyhow!("missing value"))?;
+                word.parse().with_context(|| format!("invalid number '{}'", word))
+            };
+            let opt_num = |i: usize, default: f32| if words.len() > i { num(i) } else { Ok(default) };
+            // a fraction from 0 to 1, anything outside of that is clamped.
+            let fraction = |i: usize, default: f32| -> anyhow::Result<f32> {
+                let value = opt_num(i, default)?;
+                if !value.is_finite() {
+                    return Err(anyhow!("invalid fraction '{}'", value));
+                }
+                Ok(value.clamp(0.0, 1.0))
+            };
+            let pos = Vec2::new(num(1)?, num(2)?);
+            let kind = match words[0] {
+                "spawn" => MarkerKind::PlayerSpawn,
+                "turret" => {
+                    let rot_speed = opt_num(4, DEFAULT_TURRET_ROT_SPEED)?;
+                    if !(rot_speed.is_finite() && rot_speed >= 0.0) {
+                        return Err(anyhow!("invalid turn speed '{}'", rot_speed));
+                    }
+                    MarkerKind::Turret {
+                        fire_rate: opt_num(3, DEFAULT_TURRET_FIRE_RATE)?,
+                        rot_speed,
+                        accuracy: fraction(5, DEFAULT_TURRET_ACCURACY)?,
+                        lead: fraction(6, DEFAULT_TURRET_LEAD)?,
+                        archetype: words.get(7).unwrap_or(&DEFAULT_TURRET_ARCHETYPE).to_string(),
+                    }
+                }
+                "exit" => MarkerKind::Exit,
+                other => return Err(anyhow!("unknown marker '{}'", other)),
+            };
+            Ok(Marker { pos, kind })
+        };
+        markers.push(parse_marker().with_context(|| format!("invalid marker on line {}", i + 1))?);
+    }
+    Ok(markers)
+}
+
+/// convert a level into the ascii format read by `parse_ascii`.
+/// only the sign of each density is kept, and markers are written as a list after the grid.
+pub fn to_ascii(level: &LevelData) -> String {
+    let [width, height] = level.densities.dim();
+    let mut text = String::with_capacity((width + 1) * height);
+    for y in 0..height {
+        for x in 0..width {
+            text.push(match level.densities.get([x, y]) {
+                d if d > 0 => OPEN_CHAR,
+                0 => SQUARE_WALL_CHAR,
+                _ => WALL_CHAR,
+            });
+        }
+        text.push('\n');
+    }
+    if !level.markers.is_empty() {
+        text.push('\n');
+        text.push_str(&markers_to_string(&level.markers));
+    }
+    text
+}
+
+/// convert markers into the format read by `parse_markers`.
+pub fn markers_to_string(markers: &[Marker]) -> String {
+    markers
+        .iter()
+        .map(|Marker { pos, kind }| match kind {
+            MarkerKind::PlayerSpawn => format!("spawn {} {}\n", pos.x, pos.y),
+            MarkerKind::Turret { fire_rate, rot_speed, accuracy, lead, archetype } => format!(
+                "turret {} {} {} {} {} {} {}\n",
+                pos.x, pos.y, fire_rate, rot_speed, accuracy, lead, archetype
+            ),
+            MarkerKind::Exit => format!("exit {} {}\n", pos.x, pos.y),
+        })
+        .collect()
+}
+
+/// load a grayscale image as a level. black pixels are solid walls,
+/// white pixels are open space, and mid gray (128) is a zero density wall.
+/// images can't hold markers, so they are read from a file next to the image
+/// with the `.markers` extension if it exists (see `parse_markers`).
+pub fn load_png(path: &Path) -> anyhow::Result<LevelData> {
+    let image = image::open(path)
+        .with_context(|| format!("failed to open level image {}", path.display()))?
+        .into_luma8();
+    let (width, height) = image.dimensions();
+    let mut matrix = Matrix::new([width as usize, height as usize]);
+    for (x, y, Luma([value])) in image.enumerate_pixels() {
+        matrix.set([x as usize, y as usize], (*value as i16 - 128) as i8);
+    }
+
+    let markers_path = path.with_extension("markers");
+    let markers = if markers_path.exists() {
+        let text = fs::read_to_string(&markers_path)
+            .with_context(|| format!("failed to read marker file {}", markers_path.display()))?;
+        parse_markers(&text)
+            .with_context(|| format!("failed to parse marker file {}", markers_path.display()))?
+    } else {
+        vec![]
+    };
+    Ok(LevelData::new(matrix, markers))
+}
+
+/// save a level as a grayscale image, the inverse of `load_png`.
+pub fn save_png(level: &LevelData, path: &Path) -> anyhow::Result<()> {
+    let [width, height] = level.densities.dim();
+    let mut image = GrayImage::new(width as u32, height as u32);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let density = level.densities.get([x as usize, y as usize]);
+        *pixel = Luma([(density as i16 + 128) as u8]);
+    }
+    image
+        .save(path)
+        .with_context(|| format!("failed to write level image {}", path.display()))?;
+
+    if !level.markers.is_empty() {
+        let markers_path = path.with_extension("markers");
+        fs::write(&markers_path, markers_to_string(&level.markers))
+            .with_context(|| format!("failed to write marker file {}", markers_path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: &str = "\
+#####
+#P.T#
+#+.E#
+#####
+
+turret 2 1 0.5 2 0.8 1.5 sniper
+// comments are ignored
+exit 3 2
+";
+
+    fn assert_same_level(a: &LevelData, b: &LevelData) {
+        let dim = a.densities.dim();
+        assert_eq!(dim, b.densities.dim());
+        for y in 0..dim[1] {
+            for x in 0..dim[0] {
+                assert_eq!(
+                    a.densities.get([x, y]),
+                    b.densities.get([x, y]),
+                    "[{}, {}]",
+                    x,
+                    y
+                );
+            }
+        }
+        assert_eq!(a.markers, b.markers);
+    }
+
+    fn turret(
+        pos: Vec2,
+        fire_rate: f32,
+        rot_speed: f32,
+        accuracy: f32,
+        lead: f32,
+        archetype: &str,
+    ) -> Marker {
+        Marker {
+            pos,
+            kind: MarkerKind::Turret {
+                fire_rate,
+                rot_speed,
+                accuracy,
+                lead,
+                archetype: archetype.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn ascii_round_trips() {
+        let level = parse_ascii(LEVEL).unwrap();
+        assert_eq!(level.densities.dim(), [5, 4]);
+        assert_eq!(level.densities.get([0, 0]), -1);
+        assert_eq!(level.densities.get([1, 2]), 0);
+        assert_eq!(level.densities.get([2, 1]), 1);
+        assert_eq!(level.densities.get([3, 1]), 1);
+        assert_eq!(level.markers.len(), 5);
+
+        let text = to_ascii(&level);
+        // markers in the grid are written to the marker list instead.
+        assert!(
+            text.starts_with("#####\n#...#\n#+..#\n#####\n\n"),
+            "{}",
+            text
+        );
+        assert_same_level(&level, &parse_ascii(&text).unwrap());
+    }
+
+    #[test]
+    fn ascii_rejects_bad_grids() {
+        assert!(parse_ascii("").is_err());
+        assert!(parse_ascii("###\n##\n").is_err());
+        assert!(parse_ascii("#x#\n").is_err());
+    }
+
+    #[test]
+    fn markers_round_trip() {
+        let markers = parse_markers(LEVEL.split("\n\n").nth(1).unwrap()).unwrap();
+        assert_eq!(
+            markers,
+            vec![
+                // the lead of 1.5 is clamped.
+                turret(Vec2::new(2.0, 1.0), 0.5, 2.0, 0.8, 1.0, "sniper"),
+                Marker {
+                    pos: Vec2::new(3.0, 2.0),
+                    kind: MarkerKind::Exit
+                },
+            ]
+        );
+        assert_eq!(
+            parse_markers(&markers_to_string(&markers)).unwrap(),
+            markers
+        );
+
+        // missing turret parameters fall back to the defaults.
+        assert_eq!(
+            parse_markers("turret 1.5 2 3").unwrap(),
+            vec![turret(
+                Vec2::new(1.5, 2.0),
+                3.0,
+                DEFAULT_TURRET_ROT_SPEED,
+                DEFAULT_TURRET_ACCURACY,
+                DEFAULT_TURRET_LEAD,
+                DEFAULT_TURRET_ARCHETYPE
+            )]
+        );
+        assert!(parse_markers("spawn 1").is_err());
+        assert!(parse_markers("spawn 1 x").is_err());
+        assert!(parse_markers("door 1 2").is_err());
+
+        // turrets can't turn backwards or at an unknown speed, or aim with unknown accuracy.
+        assert!(parse_markers("turret 1 2 3 -1").is_err());
+        assert!(parse_markers("turret 1 2 3 NaN").is_err());
+        assert!(parse_markers("turret 1 2 3 inf").is_err());
+        assert!(parse_markers("turret 1 2 3 4 NaN").is_err());
+        assert!(parse_markers("turret 1 2 3 4 0.5 inf").is_err());
+        let MarkerKind::Turret { accuracy, lead, .. } =
+            parse_markers("turret 1 2 3 4 -2 7").unwrap()[0].kind.clone()
+        else {
+            panic!("not a turret");
+        };
+        assert_eq!((accuracy, lead), (0.0, 1.0));
+    }
+
+    #[test]
+    fn png_round_trips_with_markers() {
+        let dir = std::env::temp_dir().join(format!("trajectory-file-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // graded densities survive, unlike in the ascii format.
+        let mut matrix = Matrix::new([4, 3]);
+        for (i, density) in [-128, -100, -1, 0, 1, 2, 50, 127, 1, 1, -5, 0]
+            .into_iter()
+            .enumerate()
+        {
+            matrix.set([i % 4, i / 4], density);
+        }
+        let level = LevelData::new(matrix, parse_ascii(LEVEL).unwrap().markers);
+        let path = dir.join("level.png");
+        save_level(&level, &path).unwrap();
+        assert!(dir.join("level.markers").exists());
+        assert_same_level(&level, &load_level(&path).unwrap());
+
+        // levels without markers don't get a marker file.
+        let bare = LevelData::new(level.densities.clone(), vec![]);
+        let bare_path = dir.join("bare.png");
+        save_level(&bare, &bare_path).unwrap();
+        assert!(!dir.join("bare.markers").exists());
+        assert_same_level(&bare, &load_level(&bare_path).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
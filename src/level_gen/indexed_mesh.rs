@@ -0,0 +1,60 @@
+use bevy::{prelude::Vec3, utils::HashMap};
+
+/// vertices closer together than this are welded into a single vertex.
+const WELD_PRECISION: f32 = 1.0 / 1024.0;
+
+/// triangle mesh where triangles share vertices,
+/// every 3 indices make up a triangle.
+#[derive(Debug, Clone, Default)]
+pub struct IndexedMesh {
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl IndexedMesh {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// iterate over the vertex indices of every triangle.
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        self.indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]])
+    }
+}
+
+/// builds an `IndexedMesh` out of triangles, welding together vertices
+/// that end up in the same spot (using a hash of their quantized positions).
+#[derive(Default)]
+pub struct MeshBuilder {
+    mesh: IndexedMesh,
+    lookup: HashMap<(i64, i64, i64), u32>,
+}
+
+impl MeshBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn vertex(&mut self, v: Vec3) -> u32 {
+        let q = (v / WELD_PRECISION).round();
+        let key = (q.x as i64, q.y as i64, q.z as i64);
+        let vertices = &mut self.mesh.vertices;
+        *self.lookup.entry(key).or_insert_with(|| {
+            vertices.push(v);
+            (vertices.len() - 1) as u32
+        })
+    }
+
+    /// add a triangle to the mesh. triangles that collapse into
+    /// a line or a point once their vertices are welded are skipped.
+    pub fn triangle(&mut self, triangle: [Vec3; 3]) {
+        let [a, b, c] = triangle.map(|v| self.vertex(v));
+        if a != b && b != c && a != c {
+            self.mesh.indices.extend([a, b, c]);
+        }
+    }
+
+    pub fn build(self) -> IndexedMesh {
+        self.mesh
+    }
+}
@@ -1,7 +1,12 @@
 use bevy::prelude::*;
 use lazy_static::lazy_static;
 
-use super::{point::Point, tiles::Tiles};
+use super::{
+    contours::{extract_contours, Contour},
+    indexed_mesh::{IndexedMesh, MeshBuilder},
+    point::Point,
+    tiles::Tiles,
+};
 
 const CORNERS_POINT: [Point<i32, 2>; 4] = {
     [
@@ -49,7 +54,7 @@ lazy_static! {
                 vec![6, 3, 7, 6, 4, 3],
                 vec![6, 4, 3, 6, 3, 1, 6, 1, 0],
                 vec![6, 4, 7, 4, 1, 7, 4, 2, 1],
-                vec![0, 6, 8, 0, 8, 2, 4, 8, 2, 6, 8, 4],
+                vec![0, 6, 8, 0, 8, 2, 2, 8, 4, 4, 8, 6],
             ],
             // zero and positive density only ruleset
             [
@@ -68,7 +73,7 @@ lazy_static! {
                 vec![],
                 vec![],
                 vec![],
-                vec![0, 6, 8, 0, 8, 2, 4, 8, 2, 6, 8, 4],
+                vec![0, 6, 8, 0, 8, 2, 2, 8, 4, 4, 8, 6],
             ]
         ]
     };
@@ -123,44 +128,319 @@ fn index_to_corner_indices(index: usize) -> [usize; 2] {
 /// modified version of the marching squares algorithm.
 /// this modified algorithm allows you to make 90 degree corners along
 /// nodes, which isn't possible with the original marching squares algorithm.
-pub fn marching_squares(tiles: &Tiles) -> (Vec<Vec3>, Vec<Vec3>) {
-    let mut collision_vertices = Vec::new();
-    let mut vertices = Vec::new();
-    for y in -2..(tiles.dimension()[1] + 1) as i32 {
-        for x in -2..(tiles.dimension()[0] + 1) as i32 {
+/// returns the mesh of the walls, with vertices shared between triangles,
+/// and the outlines of the walls as closed contours.
+pub fn marching_squares(tiles: &Tiles) -> (IndexedMesh, Vec<Contour>) {
+    let dim = tiles.dimension();
+    marching_squares_in(
+        tiles,
+        [-2, -2].into(),
+        [dim[0] as i32 + 1, dim[1] as i32 + 1].into(),
+    )
+}
+
+/// run marching squares only on the tiles whose first corner node is in `min..max`.
+/// tiles are still positioned relative to node (0, 0) of `tiles`. to find the outlines,
+/// tiles one step outside of the range are also built, so nodes from `min - 1` up to
+/// `max + 1` are read, and `tiles` needs to contain that margin around the range
+/// if the result has to line up with the geometry of neighboring ranges.
+/// outlines that cross the edge of the range are cut off there, and come out as open contours.
+pub fn marching_squares_in(
+    tiles: &Tiles,
+    min: Point<i32, 2>,
+    max: Point<i32, 2>,
+) -> (IndexedMesh, Vec<Contour>) {
+    let mut render = MeshBuilder::new();
+    let mut outline = MeshBuilder::new();
+    for y in min[1] - 1..max[1] + 1 {
+        for x in min[0] - 1..max[0] + 1 {
             let loc = [x, y].into();
-            let (ruleset, map_id) = get_ruleset_and_map_id(loc, tiles);
-            let tile_location: Point<f64, 2> = (loc,).into();
-            let tile_location = tile_location * tiles.dist_between_nodes();
-            for point in &TRIANGLE_MAPPINGS[ruleset][map_id] {
-                let rel_loc = if *point == 8 {
-                    CORNERS[0].lerp(CORNERS[2], 0.5)
-                } else {
-                    let corner_indices = index_to_corner_indices(*point);
-                    let (prop, corner_indices) = get_density_proportion(loc, corner_indices, tiles);
-                    CORNERS[corner_indices[0]].lerp(CORNERS[corner_indices[1]], prop)
-                };
-            
-                let neighbors = [
-                    [0, 0].into(),
-                    [-1, 0].into(),
-                    [0, -1].into(),
-                    [1, 0].into(),
-                    [0, 1].into()
-                ];
-
-                let l = rel_loc * tiles.dist_between_nodes() + tile_location;
-                let l = Vec3::new(l[0] as f32, -l[1] as f32, 0.0);
-                let empty_nearby = neighbors.into_iter().any(|x| {
-                    let (ruleset, map_id) = get_ruleset_and_map_id(loc + x, tiles);
-                    ruleset == 1 && map_id != 15
-                });
-                if (ruleset == 1 && map_id == 15 && empty_nearby) || (ruleset == 0 && map_id != 15) {
-                    collision_vertices.push(l);
+            let in_range = x >= min[0] && x < max[0] && y >= min[1] && y < max[1];
+            for triangle in tile_triangles(loc, tiles) {
+                if in_range {
+                    render.triangle(triangle);
+                }
+                outline.triangle(triangle);
+            }
+        }
+    }
+
+    let dist = tiles.dist_between_nodes() as f32;
+    let epsilon = dist * 1e-3;
+    let (lo, hi) = (
+        Vec2::new(min[0] as f32, min[1] as f32) * dist - epsilon,
+        Vec2::new(max[0] as f32, max[1] as f32) * dist + epsilon,
+    );
+    // edges lying exactly on the border of the range are kept by both neighboring ranges,
+    // which is better than both of them dropping it.
+    let contours = extract_contours(&outline.build(), |p| {
+        let p = Vec2::new(p.x, -p.y);
+        p.cmpge(lo).all() && p.cmple(hi).all()
+    });
+    (render.build(), contours)
+}
+
+/// find the location of one of the points of the tile layout (see `TRIANGLE_MAPPINGS`)
+/// relative to the tile, where (0, 0) is corner 0 and (1, 1) is corner 2.
+/// points on the edges are moved to where the density crosses zero between the two corners.
+fn tile_point(loc: Point<i32, 2>, point: usize, tiles: &Tiles) -> Point<f64, 2> {
+    if point == 8 {
+        return CORNERS[0].lerp(CORNERS[2], 0.5);
+    }
+    let corner_indices = index_to_corner_indices(point);
+    let (prop, corner_indices) = get_density_proportion(loc, corner_indices, tiles);
+    // `lerp` weighs the point it's called on by `prop`, so this is `prop` of the way
+    // from the first corner to the second.
+    CORNERS[corner_indices[1]].lerp(CORNERS[corner_indices[0]], prop)
+}
+
+/// build the triangles of a single tile, in world space.
+fn tile_triangles(loc: Point<i32, 2>, tiles: &Tiles) -> Vec<[Vec3; 3]> {
+    let (ruleset, map_id) = get_ruleset_and_map_id(loc, tiles);
+    let tile_location: Point<f64, 2> = (loc,).into();
+    let tile_location = tile_location * tiles.dist_between_nodes();
+    let points: Vec<Vec3> = TRIANGLE_MAPPINGS[ruleset][map_id]
+        .iter()
+        .map(|point| {
+            let l = tile_point(loc, *point, tiles) * tiles.dist_between_nodes() + tile_location;
+            Vec3::new(l[0] as f32, -l[1] as f32, 0.0)
+        })
+        .collect();
+    points.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::level_gen::matrix::Matrix;
+
+    const EPSILON: f64 = 1e-6;
+
+    /// tilemap with a single tile, where corner i of the tile has densities[i].
+    fn single_tile(densities: [i8; 4]) -> Tiles {
+        let mut matrix = Matrix::new([2, 2]);
+        for (corner, density) in CORNERS_POINT.iter().zip(densities) {
+            matrix.set([corner[0] as usize, corner[1] as usize], density);
+        }
+        Tiles::new(matrix, 1.0)
+    }
+
+    /// corner densities that produce the provided ruleset and map id.
+    fn case_densities(ruleset: usize, map_id: usize) -> [i8; 4] {
+        let solid = if ruleset == 0 { -1 } else { 0 };
+        std::array::from_fn(|i| if map_id & (1 << i) != 0 { solid } else { 1 })
+    }
+
+    /// every map id of both rulesets, except for map 0 of the default ruleset,
+    /// which can't happen since it needs a negative density but has no solid corners.
+    fn cases() -> impl Iterator<Item = (usize, usize)> {
+        (0..2)
+            .flat_map(|ruleset| (0..16).map(move |map_id| (ruleset, map_id)))
+            .filter(|case| *case != (0, 0))
+    }
+
+    /// area of a unit tile that is solid, when solid corners are -1 and the rest are 1.
+    fn solid_area(map_id: usize) -> f64 {
+        match map_id.count_ones() {
+            0 => 0.0,
+            1 => 1.0 / 8.0,
+            // opposite corners are connected through the middle of the tile.
+            2 if map_id == 5 || map_id == 10 => 3.0 / 4.0,
+            2 => 1.0 / 2.0,
+            3 => 7.0 / 8.0,
+            _ => 1.0,
+        }
+    }
+
+    /// positive for triangles that are wound counter clockwise in world space.
+    fn signed_area(t: &[Vec3; 3]) -> f64 {
+        ((t[1] - t[0]).truncate().perp_dot((t[2] - t[0]).truncate()) / 2.0) as f64
+    }
+
+    fn mesh_triangles(mesh: &IndexedMesh) -> Vec<[Vec3; 3]> {
+        mesh.triangles()
+            .map(|t| t.map(|i| mesh.vertices[i as usize]))
+            .collect()
+    }
+
+    #[test]
+    fn triangle_mappings_are_lists_of_triangles() {
+        for ruleset in TRIANGLE_MAPPINGS.iter() {
+            for (map_id, points) in ruleset.iter().enumerate() {
+                assert_eq!(
+                    points.len() % 3,
+                    0,
+                    "map {} isn't a list of triangles",
+                    map_id
+                );
+                assert!(
+                    points.iter().all(|p| *p <= 8),
+                    "map {} has an unknown point",
+                    map_id
+                );
+                if map_id != 15 {
+                    assert!(
+                        !points.contains(&8),
+                        "map {} uses the middle of the tile",
+                        map_id
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn finds_ruleset_and_map_id_for_every_case() {
+        for (ruleset, map_id) in cases() {
+            let tiles = single_tile(case_densities(ruleset, map_id));
+            assert_eq!(
+                get_ruleset_and_map_id([0, 0].into(), &tiles),
+                (ruleset, map_id)
+            );
+        }
+    }
+
+    #[test]
+    fn every_case_is_wound_consistently_and_covers_the_solid_area() {
+        for (ruleset, map_id) in cases() {
+            let tiles = single_tile(case_densities(ruleset, map_id));
+            let triangles = tile_triangles([0, 0].into(), &tiles);
+            for t in &triangles {
+                assert!(
+                    signed_area(t) > EPSILON,
+                    "ruleset {} map {} has a degenerate or backwards triangle {:?}",
+                    ruleset,
+                    map_id,
+                    t
+                );
+            }
+            let area: f64 = triangles.iter().map(signed_area).sum();
+            let expected = match (ruleset, map_id) {
+                (0, map_id) => solid_area(map_id),
+                (_, 15) => 1.0,
+                _ => 0.0,
+            };
+            assert!(
+                (area - expected).abs() < EPSILON,
+                "ruleset {} map {} covers {} instead of {}",
+                ruleset,
+                map_id,
+                area,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn random_grids_cover_the_solid_area() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let dim = [12, 9];
+        let dist = 2.0;
+        for _ in 0..20 {
+            let mut matrix = Matrix::new(dim);
+            for y in 0..dim[1] {
+                for x in 0..dim[0] {
+                    matrix.set([x, y], if rng.gen_bool(0.5) { -1 } else { 1 });
                 }
-                vertices.push(l);
+            }
+            let tiles = Tiles::new(matrix, dist);
+            // only the tiles that have all of their corners inside of the grid.
+            let max = [dim[0] as i32 - 1, dim[1] as i32 - 1];
+            let (mesh, _) = marching_squares_in(&tiles, [0, 0].into(), max.into());
+
+            let mut area = 0.0;
+            for t in mesh_triangles(&mesh) {
+                assert!(
+                    signed_area(&t) > EPSILON,
+                    "degenerate or backwards triangle {:?}",
+                    t
+                );
+                area += signed_area(&t);
+            }
+            let expected: f64 = (0..max[1])
+                .flat_map(|y| (0..max[0]).map(move |x| [x, y]))
+                .map(|loc| match get_ruleset_and_map_id(loc.into(), &tiles) {
+                    (0, map_id) => solid_area(map_id),
+                    _ => 0.0,
+                })
+                .sum::<f64>()
+                * dist
+                * dist;
+            assert!(
+                (area - expected).abs() < 1e-3,
+                "mesh covers {} instead of {}",
+                area,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn random_graded_grids_are_wound_consistently() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let dim = [10, 10];
+        for _ in 0..20 {
+            let mut matrix = Matrix::new(dim);
+            for y in 0..dim[1] {
+                for x in 0..dim[0] {
+                    matrix.set([x, y], rng.gen_range(-5..=5));
+                }
+            }
+            let (mesh, _) = marching_squares(&Tiles::new(matrix, 1.0));
+            for t in mesh_triangles(&mesh) {
+                assert!(
+                    signed_area(&t) > 0.0,
+                    "degenerate or backwards triangle {:?}",
+                    t
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn density_proportion_edge_cases() {
+        let proportion =
+            |d0, d1| get_density_proportion([0, 0].into(), [0, 1], &single_tile([d0, d1, 1, 1]));
+        assert_eq!(proportion(-1, 1), (0.5, [0, 1]));
+        assert_eq!(proportion(-1, 3), (0.25, [0, 1]));
+        assert_eq!(proportion(6, -2), (0.75, [0, 1]));
+        // a zero density pins the point to that corner.
+        assert_eq!(proportion(0, 5), (0.0, [0, 0]));
+        assert_eq!(proportion(5, 0), (1.0, [1, 1]));
+        assert_eq!(proportion(0, 0), (1.0, [1, 1]));
+        // equal densities never cross zero, so the first corner is used.
+        assert_eq!(proportion(-3, -3), (0.0, [0, 0]));
+    }
+
+    #[test]
+    fn edge_points_are_placed_where_the_density_crosses_zero() {
+        let tiles = single_tile([-1, 3, 1, 1]);
+        // point 1 is on the edge between corner 0 and corner 1.
+        assert_eq!(
+            tile_point([0, 0].into(), 1, &tiles),
+            Point::new([0.25, 0.0])
+        );
+        let tiles = single_tile([3, -1, 1, 1]);
+        assert_eq!(
+            tile_point([0, 0].into(), 1, &tiles),
+            Point::new([0.75, 0.0])
+        );
+    }
+
+    #[test]
+    fn open_node_is_outlined_by_a_closed_contour() {
+        let mut matrix = Matrix::new([5, 5]);
+        for y in 0..5 {
+            for x in 0..5 {
+                matrix.set([x, y], -1);
             }
         }
+        matrix.set([2, 2], 1);
+        let (_, contours) = marching_squares(&Tiles::new(matrix, 1.0));
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].closed);
+        assert_eq!(contours[0].points.len(), 4);
     }
-    (vertices, collision_vertices)
 }
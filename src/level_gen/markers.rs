@@ -0,0 +1,59 @@
+use bevy::prelude::Vec2;
+
+use super::matrix::Matrix;
+
+pub const DEFAULT_TURRET_FIRE_RATE: f32 = 1.0;
+pub const DEFAULT_TURRET_ROT_SPEED: f32 = 2.0;
+pub const DEFAULT_TURRET_ACCURACY: f32 = 1.0;
+pub const DEFAULT_TURRET_LEAD: f32 = 0.0;
+/// the name of the archetype of turrets that don't name one (see `archetype::Archetypes`).
+pub const DEFAULT_TURRET_ARCHETYPE: &str = "default";
+
+/// the kind of entity a marker places in the level.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkerKind {
+    PlayerSpawn,
+    Turret {
+        fire_rate: f32,
+        rot_speed: f32,
+        accuracy: f32,
+        lead: f32,
+        archetype: String,
+    },
+    Exit,
+}
+
+impl MarkerKind {
+    pub fn default_turret() -> Self {
+        MarkerKind::Turret {
+            fire_rate: DEFAULT_TURRET_FIRE_RATE,
+            rot_speed: DEFAULT_TURRET_ROT_SPEED,
+            accuracy: DEFAULT_TURRET_ACCURACY,
+            lead: DEFAULT_TURRET_LEAD,
+            archetype: DEFAULT_TURRET_ARCHETYPE.to_string(),
+        }
+    }
+}
+
+/// an entity placement embedded in the level data.
+/// `pos` is in node coordinates (the same space the density matrix is indexed in),
+/// so (1.5, 2.5) is the middle of the tile between nodes (1, 2) and (2, 3).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    pub pos: Vec2,
+    pub kind: MarkerKind,
+}
+
+/// everything needed to build a level: the densities of the terrain
+/// and the entities that should be placed in it.
+#[derive(Clone)]
+pub struct LevelData {
+    pub densities: Matrix<i8, 2>,
+    pub markers: Vec<Marker>,
+}
+
+impl LevelData {
+    pub fn new(densities: Matrix<i8, 2>, markers: Vec<Marker>) -> Self {
+        Self { densities, markers }
+    }
+}
@@ -1,5 +1,6 @@
 /// generic N dimensional matrix that can contain any types implementing
 /// the Copy and Default traits.
+#[derive(Clone)]
 pub struct Matrix<T, const N : usize> where T : Copy + Default {
     dim : [usize; N],
     elems : Vec<T>
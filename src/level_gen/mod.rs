@@ -2,4 +2,9 @@ pub mod point;
 pub mod tiles;
 pub mod matrix;
 pub mod marching_squares;
-pub mod dungeon;
\ No newline at end of file
+pub mod contours;
+pub mod dungeon;
+pub mod file;
+pub mod indexed_mesh;
+pub mod markers;
+pub mod regions;
\ No newline at end of file
@@ -0,0 +1,309 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::Vec2;
+
+use super::{matrix::Matrix, point::Point, tiles::Tiles};
+
+/// density given to nodes that are carved out to connect regions.
+const OPEN_DENSITY: i8 = 1;
+/// density given to nodes of removed regions.
+const WALL_DENSITY: i8 = -1;
+
+/// connected areas of open space (positive density) in a tilemap,
+/// found with a flood fill. nodes are connected to their 4 direct neighbors.
+pub struct Regions {
+    /// region label of every node, 0 for nodes that aren't open.
+    labels: Matrix<u32, 2>,
+    /// number of nodes in each region, indexed by label - 1.
+    sizes: Vec<usize>,
+}
+
+fn is_open(tiles: &Tiles, loc: [usize; 2]) -> bool {
+    tiles.get(to_point(loc)) > 0
+}
+
+fn to_point(loc: [usize; 2]) -> Point<i32, 2> {
+    Point::new(loc.map(|x| x as i32))
+}
+
+/// iterate over the neighbors of `loc` that are inside a matrix with dimensions `dim`.
+fn neighbors(loc: [usize; 2], dim: [usize; 2]) -> impl Iterator<Item = [usize; 2]> {
+    [[1, 0], [-1, 0], [0, 1], [0, -1]]
+        .into_iter()
+        .filter_map(move |[dx, dy]: [i32; 2]| {
+            let x = usize::try_from(loc[0] as i32 + dx).ok()?;
+            let y = usize::try_from(loc[1] as i32 + dy).ok()?;
+            (x < dim[0] && y < dim[1]).then_some([x, y])
+        })
+}
+
+impl Regions {
+    pub fn new(tiles: &Tiles) -> Self {
+        let dim = tiles.dimension();
+        let mut labels = Matrix::new(dim);
+        let mut sizes = vec![];
+        for y in 0..dim[1] {
+            for x in 0..dim[0] {
+                if labels.get([x, y]) != 0 || !is_open(tiles, [x, y]) {
+                    continue;
+                }
+                let label = sizes.len() as u32 + 1;
+                let mut size = 0;
+                let mut queue = VecDeque::from([[x, y]]);
+                labels.set([x, y], label);
+                while let Some(loc) = queue.pop_front() {
+                    size += 1;
+                    for n in neighbors(loc, dim) {
+                        if labels.get(n) == 0 && is_open(tiles, n) {
+                            labels.set(n, label);
+                            queue.push_back(n);
+                        }
+                    }
+                }
+                sizes.push(size);
+            }
+        }
+        Self { labels, sizes }
+    }
+
+    /// number of separate regions.
+    pub fn count(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// label of the region that the node at `loc` belongs to,
+    /// or None if the node isn't open or is outside of the tilemap.
+    pub fn label(&self, loc: [usize; 2]) -> Option<u32> {
+        let dim = self.labels.dim();
+        if loc[0] >= dim[0] || loc[1] >= dim[1] {
+            return None;
+        }
+        Some(self.labels.get(loc)).filter(|label| *label != 0)
+    }
+
+    /// number of nodes in the region with the provided label.
+    pub fn size(&self, label: u32) -> usize {
+        self.sizes[label as usize - 1]
+    }
+
+    /// label of the region with the most nodes.
+    pub fn largest(&self) -> Option<u32> {
+        (1..=self.count() as u32).max_by_key(|label| self.size(*label))
+    }
+
+    /// iterate over every node of the region with the provided label.
+    pub fn nodes(&self, label: u32) -> impl Iterator<Item = [usize; 2]> + '_ {
+        let [width, height] = self.labels.dim();
+        (0..height)
+            .flat_map(move |y| (0..width).map(move |x| [x, y]))
+            .filter(move |loc| self.labels.get(*loc) == label)
+    }
+
+    /// the nodes on the edge of every region, indexed by label - 1. these are the nodes
+    /// with a neighbor that is a wall or part of another region.
+    pub fn boundaries(&self) -> Vec<Vec<[usize; 2]>> {
+        let dim = self.labels.dim();
+        let mut boundaries = vec![vec![]; self.count()];
+        for y in 0..dim[1] {
+            for x in 0..dim[0] {
+                let label = self.labels.get([x, y]);
+                if label != 0 && neighbors([x, y], dim).any(|n| self.labels.get(n) != label) {
+                    boundaries[label as usize - 1].push([x, y]);
+                }
+            }
+        }
+        boundaries
+    }
+
+    /// returns true if the node nearest to `pos` (in node coordinates) is in the largest region.
+    pub fn in_largest(&self, pos: Vec2) -> bool {
+        let pos = pos.round();
+        if pos.x < 0.0 || pos.y < 0.0 {
+            return false;
+        }
+        let label = self.label([pos.x as usize, pos.y as usize]);
+        label.is_some() && label == self.largest()
+    }
+}
+
+/// finds the node of the largest region that is furthest away from any wall,
+/// which is the safest place to spawn the player. returns None if the tilemap has no open space.
+pub fn find_spawn(tiles: &Tiles, regions: &Regions) -> Option<Vec2> {
+    let largest = regions.largest()?;
+    let dim = tiles.dimension();
+
+    // breadth first search outwards from every wall, so each open node
+    // ends up with its distance (in steps) to the closest wall.
+    let mut distances: Matrix<u32, 2> = Matrix::new(dim);
+    let mut queue = VecDeque::new();
+    for y in 0..dim[1] {
+        for x in 0..dim[0] {
+            if !is_open(tiles, [x, y]) {
+                queue.push_back([x, y]);
+            } else if x == 0 || y == 0 || x == dim[0] - 1 || y == dim[1] - 1 {
+                // everything outside of the tilemap is solid.
+                distances.set([x, y], 1);
+                queue.push_back([x, y]);
+            }
+        }
+    }
+    while let Some(loc) = queue.pop_front() {
+        let dist = distances.get(loc);
+        for n in neighbors(loc, dim) {
+            if is_open(tiles, n) && distances.get(n) == 0 {
+                distances.set(n, dist + 1);
+                queue.push_back(n);
+            }
+        }
+    }
+
+    regions
+        .nodes(largest)
+        .max_by_key(|loc| distances.get(*loc))
+        .map(|[x, y]| Vec2::new(x as f32, y as f32))
+}
+
+/// fill every region except the largest one that has fewer than `min_size` nodes with walls.
+/// returns true if anything was removed.
+pub fn remove_pockets(tiles: &mut Tiles, regions: &Regions, min_size: usize) -> bool {
+    let largest = regions.largest();
+    let mut removed = false;
+    for label in 1..=regions.count() as u32 {
+        if Some(label) == largest || regions.size(label) >= min_size {
+            continue;
+        }
+        for loc in regions.nodes(label) {
+            tiles.set(to_point(loc), WALL_DENSITY);
+        }
+        removed = true;
+    }
+    removed
+}
+
+/// carve tunnels so that every region is connected to the largest region.
+/// regions are connected in order of size, each one to the closest node
+/// of the largest region or of a region that was already connected.
+pub fn connect_regions(tiles: &mut Tiles, regions: &Regions) {
+    let Some(largest) = regions.largest() else {
+        return;
+    };
+    // moving from a node towards any node outside of its region gets closer to it,
+    // so the closest nodes of two regions are always on their boundaries.
+    let mut boundaries = regions.boundaries();
+    let mut connected = std::mem::take(&mut boundaries[largest as usize - 1]);
+    let mut labels: Vec<u32> = (1..=regions.count() as u32)
+        .filter(|label| *label != largest)
+        .collect();
+    labels.sort_by_key(|label| std::cmp::Reverse(regions.size(*label)));
+
+    for label in labels {
+        let nodes = std::mem::take(&mut boundaries[label as usize - 1]);
+        let closest = nodes
+            .iter()
+            .flat_map(|from| connected.iter().map(move |to| (*from, *to)))
+            .min_by_key(|(from, to)| {
+                let dx = from[0] as i64 - to[0] as i64;
+                let dy = from[1] as i64 - to[1] as i64;
+                dx * dx + dy * dy
+            });
+        if let Some((from, to)) = closest {
+            connected.extend(carve_tunnel(tiles, from, to));
+        }
+        connected.extend(nodes);
+    }
+}
+
+/// carve an L shaped tunnel that is two nodes wide between `from` and `to`,
+/// and return every node that was carved.
+fn carve_tunnel(tiles: &mut Tiles, from: [usize; 2], to: [usize; 2]) -> Vec<[usize; 2]> {
+    let dim = tiles.dimension();
+    let (from, to) = (from.map(|x| x as i32), to.map(|x| x as i32));
+    let corner = [to[0], from[1]];
+    let mut path = vec![];
+    for (a, b) in [(from, corner), (corner, to)] {
+        let steps = (b[0] - a[0]).abs().max((b[1] - a[1]).abs());
+        let step = [(b[0] - a[0]).signum(), (b[1] - a[1]).signum()];
+        for i in 0..=steps {
+            path.push([a[0] + step[0] * i, a[1] + step[1] * i]);
+        }
+    }
+
+    let mut carved = vec![];
+    for [x, y] in path {
+        for loc in [[x, y], [x + 1, y], [x, y + 1], [x + 1, y + 1]] {
+            if loc[0] < dim[0] as i32 && loc[1] < dim[1] as i32 {
+                tiles.set(Point::new(loc), OPEN_DENSITY);
+                carved.push(loc.map(|x| x as usize));
+            }
+        }
+    }
+    carved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level_gen::file::parse_ascii;
+
+    fn tiles(text: &str) -> Tiles {
+        Tiles::new(parse_ascii(text).unwrap().densities, 1.0)
+    }
+
+    const TWO_ROOMS: &str = "\
+##############
+#......#.....#
+#......#.....#
+#......#######
+#......#######
+##############
+";
+
+    #[test]
+    fn separate_rooms_end_up_connected() {
+        let mut tiles = tiles(TWO_ROOMS);
+        let regions = Regions::new(&tiles);
+        assert_eq!(regions.count(), 2);
+        assert_eq!(regions.size(regions.largest().unwrap()), 24);
+
+        connect_regions(&mut tiles, &regions);
+        let regions = Regions::new(&tiles);
+        assert_eq!(regions.count(), 1);
+        // only a short tunnel through the wall between the rooms is carved.
+        assert!(regions.size(1) <= 24 + 10 + 4, "{}", regions.size(1));
+    }
+
+    #[test]
+    fn small_pockets_are_filled() {
+        let mut tiles = tiles(TWO_ROOMS);
+        let regions = Regions::new(&tiles);
+        assert!(!remove_pockets(&mut tiles, &regions, 10));
+        assert_eq!(Regions::new(&tiles).count(), 2);
+
+        assert!(remove_pockets(&mut tiles, &regions, 11));
+        let regions = Regions::new(&tiles);
+        assert_eq!(regions.count(), 1);
+        assert_eq!(regions.size(1), 24);
+        assert!(tiles.get(Point::new([10, 1])) < 0);
+    }
+
+    #[test]
+    fn spawn_is_in_the_middle_of_the_largest_region() {
+        let tiles = tiles(
+            "\
+###############
+#...#.........#
+#...#.........#
+#...#.........#
+#...#.........#
+#...#.........#
+###############
+",
+        );
+        let regions = Regions::new(&tiles);
+        assert_eq!(regions.count(), 2);
+        let spawn = find_spawn(&tiles, &regions).unwrap();
+        assert!(regions.in_largest(spawn));
+        assert_eq!(spawn.y, 3.0);
+        assert!((7.0..=11.0).contains(&spawn.x), "{}", spawn);
+    }
+}
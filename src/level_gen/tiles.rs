@@ -4,6 +4,7 @@ const DEFAULT_TILE_DENSITY: i8 = 0;
 
 /// square tilemap which returns a default density for
 /// indices outside it's range.
+#[derive(Clone)]
 pub struct Tiles {
     densities : Matrix<i8, 2>,
     dist_between_nodes: f64
@@ -18,7 +19,7 @@ impl Tiles {
     }
 
     pub fn get(&self, loc: Point<i32, 2>) -> i8 {
-        match [loc[0], loc[1]].map(|x| usize::try_from(x)) {
+        match [loc[0], loc[1]].map(usize::try_from) {
             [Ok(x), Ok(y)] if x < self.densities.dim()[0] && y < self.densities.dim()[1] => {
                 self.densities.get([x, y])
             }
@@ -26,6 +27,16 @@ impl Tiles {
         }
     }
 
+    /// set the density at the provided location.
+    /// locations outside of the tilemap are ignored.
+    pub fn set(&mut self, loc: Point<i32, 2>, density: i8) {
+        if let [Ok(x), Ok(y)] = [loc[0], loc[1]].map(usize::try_from) {
+            if x < self.densities.dim()[0] && y < self.densities.dim()[1] {
+                self.densities.set([x, y], density);
+            }
+        }
+    }
+
     pub fn dimension(&self) -> [usize; 2] {
         self.densities.dim()
     }
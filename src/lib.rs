@@ -0,0 +1,109 @@
+use bevy::prelude::*;
+use bevy_rapier2d::pipeline::QueryFilter;
+
+use bullet::BulletPlugin;
+use camera::CameraPlugin;
+use fixed_step::FixedStepPlugin;
+use health::HealthPlugin;
+use level::{LevelConfig, LevelPlugin};
+use level_gen::markers::LevelData;
+use player::PlayerPlugin;
+use trajectory::TrajectoryPlugin;
+use turret::TurretPlugin;
+
+pub mod archetype;
+pub mod bullet;
+pub mod camera;
+pub mod chunks;
+pub mod fixed_step;
+pub mod headless;
+pub mod health;
+pub mod hud;
+pub mod level;
+pub mod level_gen;
+pub mod mesh;
+pub mod mouse;
+pub mod player;
+pub mod replay;
+pub mod terrain;
+pub mod trajectory;
+pub mod turret;
+
+#[derive(Component)]
+pub struct Environment;
+
+/// filter for scene queries that should only find the walls of the level,
+/// and not sensors like the colliders of turrets.
+pub fn walls() -> QueryFilter<'static> {
+    QueryFilter::only_fixed().exclude_sensors()
+}
+
+/// something that hurts the player when it touches it.
+#[derive(Component, Debug)]
+pub struct Hazard {
+    pub radius: f32,
+}
+
+/// the parts of the game, in the order they run. the simulation (player, turrets and bullets)
+/// runs in `FixedUpdate` (see `fixed_step`), everything else runs in `Update`, which comes after.
+/// each plugin orders its own sets relative to the sets it depends on,
+/// so the order holds no matter which of the plugins are added.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameSet {
+    /// `Update`: turn the mouse into world coordinates, steering, and abilities.
+    /// this is the only set that reads live input, so it's disabled while replaying.
+    Input,
+    /// `FixedUpdate`: rebuild the terrain edited since the last step, before anything moves.
+    Terrain,
+    /// `FixedUpdate`: move the player and resolve bounces.
+    /// `Update`: steer during bullet time, preview the trajectory, and show when the player is hurt.
+    Player,
+    /// `FixedUpdate`: aim turrets at the player and fire bullets.
+    Turrets,
+    /// `FixedUpdate`: move bullets and check if they hit the player.
+    Bullets,
+    /// `FixedUpdate`: kill the player once it's out of health, and respawn it.
+    Health,
+    /// `Update`: edit the terrain and stream chunks in.
+    Level,
+    /// `Update`: follow the player once it has moved.
+    Camera,
+}
+
+/// the whole game. this doesn't add any of bevy's or rapier's plugins,
+/// so the same game can run in a window (see `main.rs`) or without one (see `headless`).
+pub struct GamePlugin {
+    pub level_config: LevelConfig,
+    /// the level built from `level_config`, None for endless levels.
+    pub level: Option<LevelData>,
+}
+
+impl GamePlugin {
+    /// build the level described by `level_config` (see `LevelConfig::build_level`),
+    /// so that a missing or broken level file is reported before the app starts.
+    pub fn new(level_config: LevelConfig) -> anyhow::Result<Self> {
+        let level = level_config.build_level()?;
+        Ok(Self {
+            level_config,
+            level,
+        })
+    }
+}
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            LevelPlugin {
+                config: self.level_config.clone(),
+                level: self.level.clone(),
+            },
+            PlayerPlugin,
+            TrajectoryPlugin,
+            TurretPlugin,
+            BulletPlugin,
+            HealthPlugin,
+            CameraPlugin,
+            FixedStepPlugin,
+        ));
+    }
+}
@@ -1,34 +1,32 @@
 use bevy::{prelude::*, render::{render_resource::PrimitiveTopology, mesh::Indices}};
 use bevy_rapier2d::geometry::Collider;
 
-pub fn mesh_to_collider(mesh: &Mesh) -> Collider {
-    let ind: Vec<_> = mesh.indices().unwrap().iter().collect();
-    let vertices = get_mesh_verts(mesh);
+use crate::level_gen::{contours::Contour, indexed_mesh::IndexedMesh};
+
+/// build a single polyline collider containing the segments of every contour,
+/// or None if there are no segments. unlike a trimesh, a polyline only has the
+/// surface of the walls, so shape casts always hit an edge with a well defined normal.
+pub fn contours_to_collider(contours: &[Contour]) -> Option<Collider> {
+    let mut vertices = vec![];
     let mut indices = vec![];
-    for i in 0..ind.len() / 3 {
-        indices.push([
-            ind[i * 3] as u32,
-            ind[i * 3 + 1] as u32,
-            ind[i * 3 + 2] as u32,
-        ]);
+    for contour in contours {
+        let start = vertices.len() as u32;
+        let len = contour.points.len() as u32;
+        vertices.extend(contour.points.iter().copied());
+        indices.extend((1..len).map(|i| [start + i - 1, start + i]));
+        if contour.closed && len > 2 {
+            indices.push([start + len - 1, start]);
+        }
     }
-    Collider::trimesh(vertices, indices)
-}
-
-fn get_mesh_verts(mesh: &Mesh) -> Vec<Vec2> {
-    mesh.attribute(Mesh::ATTRIBUTE_POSITION)
-        .unwrap()
-        .as_float3()
-        .unwrap()
-        .iter()
-        .map(|[x, y, _]| (*x, *y).into())
-        .collect()
+    if indices.is_empty() {
+        return None;
+    }
+    Some(Collider::polyline(vertices, Some(indices)))
 }
 
-pub fn verts_to_mesh(verts: Vec<Vec3>) -> Mesh {
+pub fn indexed_to_mesh(indexed: IndexedMesh) -> Mesh {
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    let num_verts = verts.len() as u32;
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, verts);
-    mesh.set_indices(Some(Indices::U32((0..num_verts).collect())));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, indexed.vertices);
+    mesh.set_indices(Some(Indices::U32(indexed.indices)));
     mesh
 }
@@ -3,13 +3,17 @@ use bevy::{prelude::*, window::PrimaryWindow};
 #[derive(Resource, Default)]
 pub struct MouseWorldCoords(pub Vec2);
 
+/// updates `MouseWorldCoords` from the cursor. does nothing without a window and camera,
+/// which lets the coordinates be set directly when running headless.
 pub fn mouse_world_coords(
     mut mouse_coords: ResMut<MouseWorldCoords>,
     window: Query<&Window, With<PrimaryWindow>>,
     camera: Query<(&Camera, &GlobalTransform)>,
 ) {
-    let (camera, camera_transform) = camera.single();
-    let window = window.single();
+    let (Ok((camera, camera_transform)), Ok(window)) = (camera.get_single(), window.get_single())
+    else {
+        return;
+    };
     if let Some(world_position) = window
         .cursor_position()
         .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
@@ -1,17 +1,48 @@
 use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
-use bevy_prototype_lyon::prelude::*;
 use bevy_rapier2d::{
-    geometry::Collider,
-    pipeline::QueryFilter,
+    geometry::{Collider, Toi},
+    parry::shape::{FeatureId, Polyline},
     plugin::RapierContext,
 };
 
-use crate::{bullet::bullet_mesh, mouse::MouseWorldCoords};
+use crate::{
+    bullet::bullet_mesh,
+    fixed_step::Interpolated,
+    health::Dead,
+    level::Level,
+    mouse::{mouse_world_coords, MouseWorldCoords},
+    terrain::TerrainEdit,
+    walls, GameSet,
+};
+
+/// the player, steering it during bullet time, and its abilities.
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MouseWorldCoords>()
+            .add_event::<TerrainEdit>()
+            .add_event::<SteerInput>()
+            .add_event::<BounceEvent>()
+            .configure_sets(Update, GameSet::Player.after(GameSet::Input))
+            .add_systems(FixedUpdate, player_control.in_set(GameSet::Player))
+            .add_systems(
+                Update,
+                (read_steering, carve_ability)
+                    .after(mouse_world_coords)
+                    .in_set(GameSet::Input),
+            )
+            .add_systems(Update, apply_steering.in_set(GameSet::Player));
+    }
+}
 
+/// the next wall the player will bounce off of.
 #[derive(Debug)]
 pub struct Bounce {
-    pt: Vec2,
-    dir: Vec2,
+    /// where the player will be when it touches the wall.
+    pub pt: Vec2,
+    /// the direction the player will leave in.
+    pub dir: Vec2,
 }
 
 #[derive(Component, Debug)]
@@ -25,52 +56,62 @@ pub struct Player {
     pub min_bullet_time_dist : f32,
     pub bounces_since_bullet_time : usize,
     pub health : f32,
+    pub max_health: f32,
+    /// seconds left until the player can be hurt again.
+    pub invulnerable: f32,
 }
 
+/// moves the player by one fixed step. finds the next bounce along the player's direction,
+/// and bounces once the player passes it. bullet time starts when the player gets close
+/// to a bounce after bouncing a few times.
+///
+/// a player that left the level is moved back inside of it (if there is a `Level`), and
+/// a player that ended up inside of a wall is pushed back out, with a `BounceEvent` sent for each.
+/// if there's no wall ahead the player keeps going until one shows up.
 pub fn player_control(
+    fixed_time: Res<Time<Fixed>>,
     mut time: ResMut<Time<Virtual>>,
-    mouse: Res<Input<MouseButton>>,
-    mouse_coords: Res<MouseWorldCoords>,
     rapier_context: Res<RapierContext>,
-    mut player: Query<(&mut Transform, &mut Player)>,
-    mut line: Query<(&mut Transform, &mut Path), Without<Player>>,
+    level: Option<Res<Level>>,
+    mut events: EventWriter<BounceEvent>,
+    mut no_wall_ahead: Local<bool>,
+    mut player: Query<(&mut Transform, &mut Interpolated, &mut Player), Without<Dead>>,
 ) {
-    let (mut transform, mut player) = player.single_mut();
+    let Ok((mut transform, mut interpolated, mut player)) = player.get_single_mut() else {
+        return;
+    };
     transform.rotation = Quat::from_axis_angle(
         Vec3::new(0., 0., 1.),
         player.dir.y.atan2(player.dir.x) - std::f32::consts::PI / 2.0,
     );
 
-    let position = Vec2::new(transform.translation.x, transform.translation.y);
+    let mut position = Vec2::new(transform.translation.x, transform.translation.y);
 
-    let shape_cast = rapier_context.cast_shape(
-        position,
-        0.0,
-        player.dir.normalize(),
-        &Collider::ball(player.radius),
-        f32::MAX,
-        false,
-        QueryFilter::only_fixed(),
-    );
-
-    if let Some(bounce) = &player.bounce {
-        let (mut loc, mut line) = line.single_mut();
-        loc.translation = Vec3::new(bounce.pt.x, bounce.pt.y, 5.0);
-        let mut path_builder = PathBuilder::new();
-        if player.bullet_time {
-            path_builder.move_to(Vec2::ZERO);
-            path_builder.line_to(bounce.dir.normalize() * 25.0);
-            path_builder.close();
+    if let Some(bounds) = level.and_then(|level| level.bounds()) {
+        if !bounds.contains(position) {
+            let to = position.clamp(bounds.min, bounds.max);
+            events.send(BounceEvent::OutOfBounds { from: position, to });
+            // bounce off of the edge of the level, in case there's no wall to stop the player.
+            let outward = position - to;
+            if outward.x * player.dir.x > 0.0 {
+                player.dir.x = -player.dir.x;
+            }
+            if outward.y * player.dir.y > 0.0 {
+                player.dir.y = -player.dir.y;
+            }
+            transform.translation = to.extend(transform.translation.z);
+            interpolated.teleport(*transform);
+            player.bounce = None;
+            position = to;
         }
-        path_builder.close();
-        *line = path_builder.build();
-
+    }
 
+    if let Some(bounce) = &player.bounce {
         if player.dir.dot(bounce.pt - position) < 0.0 {
             transform.translation = Vec3::new(bounce.pt.x, bounce.pt.y, 0.0);
             player.dir = bounce.dir;
             player.bounce = None;
-            let delta = player.dir.normalize() * player.speed * time.delta_seconds();
+            let delta = player.dir.normalize() * player.speed * fixed_time.delta_seconds();
             transform.translation += Vec3::new(delta.x, delta.y, 0.0);
 
             if player.bullet_time {
@@ -82,53 +123,57 @@ pub fn player_control(
             }
             return
         }
-    } else {
-        if let Some((_, ray_intersection)) = &shape_cast {
-            if let Some(details) = &ray_intersection.details {
-                player.bounce = Some(Bounce {
-                    pt: position + ray_intersection.toi * player.dir.normalize(),
-                    dir: get_bounce_vector(player.dir, details.normal2),
-                });
-                return
-            } else {
-                if let Some((_, ray_intersection)) = rapier_context.cast_ray_and_get_normal(
-                    position,
-                    player.dir.normalize(),
-                    f32::MAX,
-                    false,
-                    QueryFilter::only_fixed(),
-                ) {
-                    player.bounce = Some(Bounce {
-                        pt: position + ray_intersection.toi * player.dir.normalize(),
-                        dir: get_bounce_vector(player.dir, ray_intersection.normal),
-                    });
-                    return
-                } else {
-                    panic!("UH OH! CAN'T FIND BOUNCE!")
-                }
-            }
-        }
     }
 
-    if player.bullet_time {
-        if let Some(bounce) = &mut player.bounce {
-            let dir = (mouse_coords.0 - bounce.pt).normalize();
-            if rapier_context.cast_ray(
-                bounce.pt,
-                dir,
-                25.0,
-                true,
-                QueryFilter::only_fixed(),
-            ).is_none() {
-                bounce.dir = dir;
-            }
+    // the player is allowed to overshoot a bounce by a step, so this is only checked once
+    // the bounce the player passed has been handled.
+    if let Some(wall) = nearest_wall(&rapier_context, position) {
+        if wall.inside {
+            let to = push_out_of_wall(&mut player, &wall);
+            events.send(BounceEvent::InsideWall { from: position, to });
+            transform.translation = to.extend(transform.translation.z);
+            return;
         }
-        if mouse.just_pressed(MouseButton::Left) {
-            player.bullet_time = false;
-            player.bounces_since_bullet_time = 0;
-            time.set_relative_speed(1.0);
+    }
+
+    let shape_cast = rapier_context.cast_shape(
+        position,
+        0.0,
+        player.dir.normalize(),
+        &Collider::ball(player.radius),
+        f32::MAX,
+        false,
+        walls(),
+    );
+
+    // the shape cast now and then misses walls that are far away, so a ray has to miss too.
+    let open_space = player.bounce.is_none()
+        && shape_cast.is_none()
+        && rapier_context
+            .cast_ray(position, player.dir.normalize(), f32::MAX, false, walls())
+            .is_none();
+    if open_space && !*no_wall_ahead {
+        events.send(BounceEvent::NoWallAhead {
+            pos: position,
+            dir: player.dir,
+        });
+    }
+    *no_wall_ahead = open_space;
+
+    if let Some((_, hit)) = shape_cast.filter(|_| player.bounce.is_none()) {
+        if let Some(bounce) = bounce_off_hit(&rapier_context, position, player.dir, &hit) {
+            player.bounce = Some(bounce);
+            return
+        } else if let Some(wall) = nearest_wall(&rapier_context, position) {
+            // the player overlaps a wall it's moving into, but its center is still outside.
+            let to = push_out_of_wall(&mut player, &wall);
+            events.send(BounceEvent::InsideWall { from: position, to });
+            transform.translation = to.extend(transform.translation.z);
+            return
         }
-    } else {
+    }
+
+    if !player.bullet_time {
         if let Some(bounce) = &mut player.bounce {
             let dist = bounce.pt.distance(transform.translation.xy().into());
             if dist < player.max_bullet_time_dist && dist > player.min_bullet_time_dist && player.bounces_since_bullet_time > 2 {
@@ -138,12 +183,202 @@ pub fn player_control(
         }
     }
 
-    let delta = player.dir.normalize() * player.speed * time.delta_seconds();
+    let delta = player.dir.normalize() * player.speed * fixed_time.delta_seconds();
     transform.translation += Vec3::new(delta.x, delta.y, 0.0);
 
 
 }
 
+/// the bounce off of the wall that a ball cast from `pos` along `dir` hit.
+/// a ball that starts out overlapping the wall doesn't get a normal from the cast,
+/// so a ray is cast to find the wall instead. None if the ray misses too,
+/// which happens when the center of the ball is already past the surface.
+pub fn bounce_off_hit(
+    rapier_context: &RapierContext,
+    pos: Vec2,
+    dir: Vec2,
+    hit: &Toi,
+) -> Option<Bounce> {
+    if let Some(details) = &hit.details {
+        return Some(Bounce {
+            pt: pos + hit.toi * dir.normalize(),
+            dir: get_bounce_vector(dir, details.normal2),
+        });
+    }
+    let (_, ray_intersection) = rapier_context.cast_ray_and_get_normal(
+        pos,
+        dir.normalize(),
+        f32::MAX,
+        false,
+        walls(),
+    )?;
+    Some(Bounce {
+        pt: pos + ray_intersection.toi * dir.normalize(),
+        dir: get_bounce_vector(dir, ray_intersection.normal),
+    })
+}
+
+/// something went wrong while moving the player, and how it was dealt with.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub enum BounceEvent {
+    /// the player left the level at `from`, and was moved back inside of it at `to`,
+    /// bouncing off of the edge of the level.
+    OutOfBounds { from: Vec2, to: Vec2 },
+    /// the player was in a wall at `from`, and was pushed out to `to`,
+    /// bouncing off of the wall if it was moving into it.
+    InsideWall { from: Vec2, to: Vec2 },
+    /// there's no wall in front of the player anymore, so it won't bounce until there is.
+    NoWallAhead { pos: Vec2, dir: Vec2 },
+}
+
+/// the point on the walls closest to some position.
+struct NearestWall {
+    point: Vec2,
+    /// points out of the wall, towards open space.
+    normal: Vec2,
+    /// whether the position is on the solid side of the wall.
+    inside: bool,
+}
+
+fn nearest_wall(rapier_context: &RapierContext, pos: Vec2) -> Option<NearestWall> {
+    let (entity, projection, feature) =
+        rapier_context.project_point_and_get_feature(pos, walls())?;
+    let offset = pos - projection.point;
+    let polyline = rapier_context
+        .entity2collider()
+        .get(&entity)
+        .and_then(|&handle| rapier_context.colliders.get(handle))
+        .and_then(|collider| collider.shape().as_polyline());
+    let inside = match polyline {
+        Some(polyline) => inside_polyline(polyline, feature, offset),
+        None => projection.is_inside,
+    };
+    Some(NearestWall {
+        point: projection.point,
+        normal: if inside { -offset } else { offset }.normalize_or_zero(),
+        inside,
+    })
+}
+
+/// whether `offset` from the point nearest to it on `feature` points into the wall.
+/// walls are on the left of every segment, since contours keep the direction their edges
+/// have in the counter clockwise triangles of the terrain mesh.
+/// the ends of open contours (where terrain regions meet) are never counted as inside,
+/// since the segment that continues the wall is part of another collider.
+fn inside_polyline(polyline: &Polyline, feature: FeatureId, offset: Vec2) -> bool {
+    let dir = |[a, b]: [u32; 2]| {
+        let (a, b) = (polyline.vertices()[a as usize], polyline.vertices()[b as usize]);
+        Vec2::new(b.x - a.x, b.y - a.y)
+    };
+    let left = |segment: Vec2| segment.perp_dot(offset) > 0.0;
+    match feature {
+        FeatureId::Face(i) => left(dir(polyline.indices()[i as usize])),
+        FeatureId::Vertex(v) => {
+            let incoming = polyline.indices().iter().find(|[_, to]| *to == v);
+            let outgoing = polyline.indices().iter().find(|[from, _]| *from == v);
+            let (Some(&incoming), Some(&outgoing)) = (incoming, outgoing) else {
+                return false;
+            };
+            let (incoming, outgoing) = (dir(incoming), dir(outgoing));
+            if incoming.perp_dot(outgoing) > 0.0 {
+                // the wall comes to a point here, so it only covers what's left of both segments.
+                left(incoming) && left(outgoing)
+            } else {
+                left(incoming) || left(outgoing)
+            }
+        }
+        _ => false,
+    }
+}
+
+/// move the player out of `wall` so it just touches its surface, bouncing off of it if it's
+/// heading further into it. returns where the player ends up.
+fn push_out_of_wall(player: &mut Player, wall: &NearestWall) -> Vec2 {
+    let normal = if wall.normal == Vec2::ZERO {
+        -player.dir.normalize()
+    } else {
+        wall.normal
+    };
+    if player.dir.dot(normal) < 0.0 {
+        player.dir = get_bounce_vector(player.dir, normal);
+    }
+    player.bounce = None;
+    wall.point + normal * player.radius
+}
+
+/// how the player steers during bullet time.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub enum SteerInput {
+    /// point the bounce the player is heading for in this direction.
+    Aim(Vec2),
+    /// end bullet time.
+    Confirm,
+}
+
+/// turns the mouse into steering input during bullet time. this runs every frame instead of
+/// every fixed step, since hardly any fixed steps run while time is slowed down.
+pub fn read_steering(
+    mouse: Res<Input<MouseButton>>,
+    mouse_coords: Res<MouseWorldCoords>,
+    rapier_context: Res<RapierContext>,
+    player: Query<&Player>,
+    mut steering: EventWriter<SteerInput>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    if !player.bullet_time {
+        return;
+    }
+    if let Some(bounce) = &player.bounce {
+        let dir = (mouse_coords.0 - bounce.pt).normalize();
+        if dir != bounce.dir && rapier_context.cast_ray(
+            bounce.pt,
+            dir,
+            25.0,
+            true,
+            walls(),
+        ).is_none() {
+            steering.send(SteerInput::Aim(dir));
+        }
+    }
+    if mouse.just_pressed(MouseButton::Left) {
+        steering.send(SteerInput::Confirm);
+    }
+}
+
+pub fn apply_steering(
+    mut time: ResMut<Time<Virtual>>,
+    mut steering: EventReader<SteerInput>,
+    mut player: Query<&mut Player>,
+) {
+    let Ok(mut player) = player.get_single_mut() else {
+        return;
+    };
+    for input in steering.read() {
+        apply_steer_input(&mut player, &mut time, *input);
+    }
+}
+
+/// steer the player, if it's in bullet time.
+pub fn apply_steer_input(player: &mut Player, time: &mut Time<Virtual>, input: SteerInput) {
+    if !player.bullet_time {
+        return;
+    }
+    match input {
+        SteerInput::Aim(dir) => {
+            if let Some(bounce) = &mut player.bounce {
+                bounce.dir = dir;
+            }
+        }
+        SteerInput::Confirm => {
+            player.bullet_time = false;
+            player.bounces_since_bullet_time = 0;
+            time.set_relative_speed(1.0);
+        }
+    }
+}
+
 impl Player {
     pub fn new() -> Self {
         Self {
@@ -155,29 +390,56 @@ impl Player {
             dir: Vec2::new(1.0, -0.5),
             radius: 5.,
             bounces_since_bullet_time : 0,
-            health : 2.0
+            health : 2.0,
+            max_health: 2.0,
+            invulnerable: 0.0,
         }
     }
 }
 
+impl Default for Player {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// radius of the hole the player carves into the terrain with the right mouse button.
+const CARVE_RADIUS: f32 = 30.0;
+
+/// carve a hole in the terrain at the mouse when the right mouse button is pressed.
+pub fn carve_ability(
+    mouse: Res<Input<MouseButton>>,
+    mouse_coords: Res<MouseWorldCoords>,
+    mut edits: EventWriter<TerrainEdit>,
+) {
+    if mouse.just_pressed(MouseButton::Right) {
+        edits.send(TerrainEdit::carve(mouse_coords.0, CARVE_RADIUS));
+    }
+}
+
 pub fn get_bounce_vector(dir: Vec2, normal: Vec2) -> Vec2 {
     dir - 2.0 * dir.dot(normal) * normal
 }
 
-pub fn setup_player(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
+pub fn spawn_player(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    pos: Vec2,
+) -> Entity {
     let player = Player::new();
     let mesh = bullet_mesh();
-    commands.spawn((
-        MaterialMesh2dBundle {
-            mesh: meshes.add(mesh).into(),
-            material: materials.add(ColorMaterial::from(Color::BLACK)),
-            transform: Transform::from_translation(Vec3::new(10.0, -10.0, 1.0)),
-            ..default()
-        },
-        player,
-    ));
+    let transform = Transform::from_translation(pos.extend(1.0));
+    commands
+        .spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(mesh).into(),
+                material: materials.add(ColorMaterial::from(Color::BLACK)),
+                transform,
+                ..default()
+            },
+            player,
+            Interpolated::new(transform),
+        ))
+        .id()
 }
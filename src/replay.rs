@@ -0,0 +1,289 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    archetype::Archetypes,
+    fixed_step::StepCount,
+    level::LevelConfig,
+    player::{apply_steer_input, Player, SteerInput},
+    terrain::{Terrain, TerrainEdit},
+    GameSet,
+};
+
+/// everything needed to play a run again: the level it was played on (including the seed),
+/// the turret archetypes it was played with, the steering input given during bullet time,
+/// and the edits made to the terrain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub level: LevelConfig,
+    pub archetypes: Archetypes,
+    pub inputs: Vec<RecordedInput>,
+    #[serde(default)]
+    pub edits: Vec<RecordedEdit>,
+}
+
+/// an input, along with the number of fixed steps that had run when it was given.
+/// on playback it's applied right before the next step.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RecordedInput {
+    pub step: u64,
+    pub input: ReplayInput,
+}
+
+/// `SteerInput` as it's stored in replay files.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReplayInput {
+    Aim { x: f32, y: f32 },
+    Confirm,
+}
+
+impl From<SteerInput> for ReplayInput {
+    fn from(input: SteerInput) -> Self {
+        match input {
+            SteerInput::Aim(dir) => ReplayInput::Aim { x: dir.x, y: dir.y },
+            SteerInput::Confirm => ReplayInput::Confirm,
+        }
+    }
+}
+
+impl From<ReplayInput> for SteerInput {
+    fn from(input: ReplayInput) -> Self {
+        match input {
+            ReplayInput::Aim { x, y } => SteerInput::Aim(Vec2::new(x, y)),
+            ReplayInput::Confirm => SteerInput::Confirm,
+        }
+    }
+}
+
+/// a `TerrainEdit`, along with the number of fixed steps that had run when it was made.
+/// on playback it's applied at the start of the next step, so the terrain is rebuilt
+/// before that step (see `GameSet::Terrain`), just like it was when recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEdit {
+    pub step: u64,
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub density: i8,
+}
+
+impl RecordedEdit {
+    pub fn new(step: u64, edit: &TerrainEdit) -> Self {
+        Self {
+            step,
+            x: edit.center.x,
+            y: edit.center.y,
+            radius: edit.radius,
+            density: edit.density,
+        }
+    }
+}
+
+impl From<RecordedEdit> for TerrainEdit {
+    fn from(edit: RecordedEdit) -> Self {
+        Self {
+            center: Vec2::new(edit.x, edit.y),
+            radius: edit.radius,
+            density: edit.density,
+        }
+    }
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read replay {}", path.display()))?;
+        ron::from_str(&text).with_context(|| format!("failed to parse replay {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text).with_context(|| format!("failed to write replay {}", path.display()))
+    }
+
+    /// turrets fire differently with other archetypes, so the run wouldn't play out the same.
+    pub fn check_archetypes(&self, archetypes: &Archetypes) -> anyhow::Result<()> {
+        if self.archetypes != *archetypes {
+            return Err(anyhow!(
+                "the replay was recorded with different turret archetypes than the ones loaded"
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub enum ReplayMode {
+    /// record the run, and keep the replay at this path up to date while playing,
+    /// so it's still there if the game crashes.
+    Record(PathBuf),
+    /// play a recorded run, ignoring live input.
+    Play(Replay),
+}
+
+impl ReplayMode {
+    /// takes `--record <path>` or `--replay <path>` out of the command line arguments.
+    pub fn from_args(args: &mut Vec<String>) -> anyhow::Result<Option<Self>> {
+        let mut mode = None;
+        while let Some(i) = args
+            .iter()
+            .position(|arg| arg == "--record" || arg == "--replay")
+        {
+            let arg = args.remove(i);
+            if i >= args.len() {
+                return Err(anyhow!("missing value for argument {}", arg));
+            }
+            if mode.is_some() {
+                return Err(anyhow!("only one of --record and --replay can be used"));
+            }
+            let path = PathBuf::from(args.remove(i));
+            mode = Some(if arg == "--record" {
+                ReplayMode::Record(path)
+            } else {
+                ReplayMode::Play(Replay::load(&path)?)
+            });
+        }
+        Ok(mode)
+    }
+}
+
+/// records or plays back a run (see `Replay`).
+pub struct ReplayPlugin {
+    pub mode: ReplayMode,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match &self.mode {
+            ReplayMode::Record(path) => {
+                app.insert_resource(Recorder {
+                    path: path.clone(),
+                    inputs: vec![],
+                    edits: vec![],
+                    dirty: true,
+                })
+                .add_systems(Update, record_inputs.after(GameSet::Input))
+                .add_systems(Last, save_recording);
+            }
+            ReplayMode::Play(replay) => {
+                app.insert_resource(Playback {
+                    inputs: replay.inputs.clone(),
+                    next: 0,
+                    edits: replay.edits.clone(),
+                    next_edit: 0,
+                })
+                .configure_sets(Update, GameSet::Input.run_if(|| false))
+                .add_systems(
+                    FixedUpdate,
+                    (
+                        play_inputs.before(GameSet::Player),
+                        play_edits.before(GameSet::Terrain),
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// the inputs and edits recorded so far.
+#[derive(Resource, Debug)]
+pub struct Recorder {
+    pub path: PathBuf,
+    pub inputs: Vec<RecordedInput>,
+    pub edits: Vec<RecordedEdit>,
+    /// whether there are inputs that haven't been saved yet.
+    dirty: bool,
+}
+
+/// the inputs and edits of the replay being played, and the index of the next of each to apply.
+#[derive(Resource, Debug)]
+pub struct Playback {
+    pub inputs: Vec<RecordedInput>,
+    pub next: usize,
+    pub edits: Vec<RecordedEdit>,
+    pub next_edit: usize,
+}
+
+fn record_inputs(
+    step: Res<StepCount>,
+    mut steering: EventReader<SteerInput>,
+    mut edits: EventReader<TerrainEdit>,
+    mut recorder: ResMut<Recorder>,
+) {
+    for input in steering.read() {
+        recorder.inputs.push(RecordedInput {
+            step: step.0,
+            input: (*input).into(),
+        });
+        recorder.dirty = true;
+    }
+    for edit in edits.read() {
+        recorder.edits.push(RecordedEdit::new(step.0, edit));
+        recorder.dirty = true;
+    }
+}
+
+fn save_recording(
+    config: Res<LevelConfig>,
+    archetypes: Res<Archetypes>,
+    mut recorder: ResMut<Recorder>,
+) {
+    if !recorder.dirty {
+        return;
+    }
+    recorder.dirty = false;
+    let replay = Replay {
+        level: config.clone(),
+        archetypes: archetypes.clone(),
+        inputs: recorder.inputs.clone(),
+        edits: recorder.edits.clone(),
+    };
+    if let Err(err) = replay.save(&recorder.path) {
+        error!("{:#}", err);
+    }
+}
+
+/// applies the inputs given after the last step, so they land
+/// between the same two steps as when they were recorded.
+fn play_inputs(
+    step: Res<StepCount>,
+    mut time: ResMut<Time<Virtual>>,
+    mut playback: ResMut<Playback>,
+    mut player: Query<&mut Player>,
+) {
+    let Ok(mut player) = player.get_single_mut() else {
+        return;
+    };
+    while let Some(recorded) = playback.inputs.get(playback.next).copied() {
+        if recorded.step > step.0 {
+            break;
+        }
+        apply_steer_input(&mut player, &mut time, recorded.input.into());
+        playback.next += 1;
+    }
+}
+
+/// applies the edits made after the last step, so the terrain changes
+/// between the same two steps as when they were recorded.
+fn play_edits(
+    step: Res<StepCount>,
+    mut playback: ResMut<Playback>,
+    terrain: Option<ResMut<Terrain>>,
+) {
+    let Some(mut terrain) = terrain else {
+        return;
+    };
+    while let Some(recorded) = playback.edits.get(playback.next_edit).copied() {
+        if recorded.step > step.0 {
+            break;
+        }
+        let edit = TerrainEdit::from(recorded);
+        terrain.set_circle(edit.center, edit.radius, edit.density);
+        playback.next_edit += 1;
+    }
+}
@@ -0,0 +1,238 @@
+use bevy::{
+    prelude::*,
+    sprite::Mesh2dHandle,
+    utils::{HashMap, HashSet},
+};
+use bevy_rapier2d::{
+    geometry::Collider,
+    plugin::RapierContext,
+    prelude::RapierColliderHandle,
+};
+
+use crate::{
+    level::spawn_terrain,
+    level_gen::{
+        contours::Contour, indexed_mesh::IndexedMesh, marching_squares::marching_squares_in,
+        point::Point, tiles::Tiles,
+    },
+    mesh::{contours_to_collider, indexed_to_mesh},
+};
+
+/// number of tiles along each side of a terrain region.
+pub const REGION_SIZE: i32 = 16;
+
+/// the densities of the level, split into square regions that are
+/// each built into their own mesh and collider. when densities change, only the
+/// regions whose geometry depends on the changed nodes are rebuilt.
+#[derive(Resource)]
+pub struct Terrain {
+    tiles: Tiles,
+    /// the densities before any edits.
+    original: Tiles,
+    region_size: i32,
+    regions: HashMap<IVec2, Entity>,
+    dirty: HashSet<IVec2>,
+}
+
+/// a piece of the terrain, region (x, y) contains the tiles from node
+/// (x, y) * REGION_SIZE up to (but not including) node (x + 1, y + 1) * REGION_SIZE.
+#[derive(Component, Debug)]
+pub struct TerrainRegion;
+
+/// request to set the density of every node within `radius` of `center` (in world space).
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TerrainEdit {
+    pub center: Vec2,
+    pub radius: f32,
+    pub density: i8,
+}
+
+impl TerrainEdit {
+    /// turn the nodes in the circle into open space.
+    pub fn carve(center: Vec2, radius: f32) -> Self {
+        Self {
+            center,
+            radius,
+            density: 1,
+        }
+    }
+}
+
+impl Terrain {
+    pub fn new(tiles: Tiles, region_size: i32) -> Self {
+        Self {
+            original: tiles.clone(),
+            tiles,
+            region_size,
+            regions: HashMap::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    /// range of tiles that marching squares needs to run over to build the whole level,
+    /// which includes the solid border around the tilemap.
+    fn tile_range(&self) -> (IVec2, IVec2) {
+        let dim = self.tiles.dimension();
+        (IVec2::splat(-2), IVec2::new(dim[0] as i32 + 1, dim[1] as i32 + 1))
+    }
+
+    /// range of tiles covered by the region at `coord`.
+    fn region_range(&self, coord: IVec2) -> (IVec2, IVec2) {
+        let (min, max) = self.tile_range();
+        let start = coord * self.region_size;
+        (start.max(min), (start + IVec2::splat(self.region_size)).min(max))
+    }
+
+    /// coordinates of every region needed to cover the level.
+    pub fn region_coords(&self) -> Vec<IVec2> {
+        let (min, max) = self.tile_range();
+        let min = self.region_of(min);
+        let max = self.region_of(max - IVec2::ONE);
+        (min.y..=max.y)
+            .flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .collect()
+    }
+
+    fn region_of(&self, tile: IVec2) -> IVec2 {
+        IVec2::new(
+            tile.x.div_euclid(self.region_size),
+            tile.y.div_euclid(self.region_size),
+        )
+    }
+
+    /// converts a world position into node coordinates.
+    pub fn world_to_node(&self, pos: Vec2) -> Vec2 {
+        Vec2::new(pos.x, -pos.y) / self.tiles.dist_between_nodes() as f32
+    }
+
+    /// set the density of a single node, and mark every region that reads it as dirty.
+    pub fn set_density(&mut self, node: IVec2, density: i8) {
+        let loc = Point::new([node.x, node.y]);
+        if self.tiles.get(loc) == density {
+            return;
+        }
+        self.tiles.set(loc, density);
+        // tiles read the nodes on their corners, and the outlines of a region
+        // also look at the tiles just outside of it.
+        let min = self.region_of(node - IVec2::splat(2));
+        let max = self.region_of(node + IVec2::ONE);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let coord = IVec2::new(x, y);
+                if self.regions.contains_key(&coord) {
+                    self.dirty.insert(coord);
+                }
+            }
+        }
+    }
+
+    /// set the density of every node within `radius` of `center`, both in world space.
+    pub fn set_circle(&mut self, center: Vec2, radius: f32, density: i8) {
+        let center = self.world_to_node(center);
+        let radius = radius / self.tiles.dist_between_nodes() as f32;
+        let min = (center - radius).floor().as_ivec2();
+        let max = (center + radius).ceil().as_ivec2();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let node = IVec2::new(x, y);
+                if node.as_vec2().distance_squared(center) <= radius * radius {
+                    self.set_density(node, density);
+                }
+            }
+        }
+    }
+
+    /// whether there are regions waiting to be rebuilt.
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// undo every edit made to the densities since the terrain was built.
+    pub fn reset(&mut self) {
+        let [width, height] = self.tiles.dimension();
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let density = self.original.get(Point::new([x, y]));
+                self.set_density(IVec2::new(x, y), density);
+            }
+        }
+    }
+
+    fn geometry(&self, coord: IVec2) -> (IndexedMesh, Vec<Contour>) {
+        let (min, max) = self.region_range(coord);
+        marching_squares_in(&self.tiles, [min.x, min.y].into(), [max.x, max.y].into())
+    }
+}
+
+/// spawn an entity with a mesh and collider for every region of the terrain.
+pub fn spawn_terrain_regions(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    terrain: &mut Terrain,
+) {
+    for coord in terrain.region_coords() {
+        let (mesh, contours) = terrain.geometry(coord);
+        let entity = spawn_terrain(
+            commands,
+            meshes,
+            materials,
+            mesh,
+            contours,
+            Transform::default(),
+        );
+        commands.entity(entity).insert(TerrainRegion);
+        terrain.regions.insert(coord, entity);
+    }
+}
+
+pub fn apply_terrain_edits(mut edits: EventReader<TerrainEdit>, mut terrain: ResMut<Terrain>) {
+    for edit in edits.read() {
+        terrain.set_circle(edit.center, edit.radius, edit.density);
+    }
+}
+
+/// rebuild the mesh and collider of every region whose densities changed.
+pub fn rebuild_terrain(
+    mut commands: Commands,
+    mut terrain: ResMut<Terrain>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    regions: Query<&Mesh2dHandle, With<TerrainRegion>>,
+) {
+    if terrain.dirty.is_empty() {
+        return;
+    }
+    let dirty: Vec<IVec2> = terrain.dirty.drain().collect();
+    for coord in dirty {
+        let Some(&entity) = terrain.regions.get(&coord) else {
+            continue;
+        };
+        let (mesh, contours) = terrain.geometry(coord);
+        if let Some(asset) = regions.get(entity).ok().and_then(|handle| meshes.get_mut(&handle.0)) {
+            *asset = indexed_to_mesh(mesh);
+        }
+        match contours_to_collider(&contours) {
+            Some(collider) => commands.entity(entity).insert(collider),
+            None => commands.entity(entity).remove::<Collider>(),
+        };
+    }
+}
+
+/// the regions whose collider was rebuilt since the last sync.
+type RebuiltRegions = (With<TerrainRegion>, Changed<Collider>);
+
+/// copy the rebuilt colliders of the terrain into rapier, and update its query pipeline.
+/// rapier only does this once a frame, after all of the frame's fixed steps have run,
+/// so terrain rebuilt between two steps of the same frame would otherwise go unseen.
+/// run after rapier's `init_colliders` and `sync_removals`, which add and remove the colliders.
+pub fn sync_terrain_colliders(
+    mut context: ResMut<RapierContext>,
+    regions: Query<(&RapierColliderHandle, &Collider), RebuiltRegions>,
+) {
+    for (handle, collider) in &regions {
+        if let Some(co) = context.colliders.get_mut(handle.0) {
+            co.set_shape(collider.raw.clone());
+        }
+    }
+    context.update_query_pipeline();
+}
@@ -0,0 +1,237 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use bevy_rapier2d::{geometry::Collider, plugin::RapierContext};
+
+use crate::{
+    player::{apply_steering, bounce_off_hit, Player},
+    walls, GameSet, Hazard,
+};
+
+/// number of bounces the preview follows after the one the player is heading for.
+pub const PREVIEW_BOUNCES: usize = 4;
+/// distance the preview follows the path for past the next bounce, fading out along the way.
+pub const PREVIEW_LENGTH: f32 = 1500.0;
+/// the preview is drawn as dashes, each one a little fainter than the one before it.
+const DASH_LENGTH: f32 = 20.0;
+const DASH_GAP: f32 = 10.0;
+const DASHES: usize = (PREVIEW_LENGTH / (DASH_LENGTH + DASH_GAP)) as usize;
+/// how close a hazard has to come to the player along the path to be marked,
+/// on top of the radius of the two.
+const HAZARD_WARNING_DIST: f32 = 30.0;
+
+/// shows where the player will go while steering in bullet time.
+pub struct TrajectoryPlugin;
+
+impl Plugin for TrajectoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Trajectory>()
+            .add_systems(Startup, setup_trajectory_preview)
+            .add_systems(
+                Update,
+                (predict_trajectory, draw_trajectory)
+                    .chain()
+                    .after(apply_steering)
+                    .in_set(GameSet::Player),
+            );
+    }
+}
+
+/// the path the player will take if bullet time ended now.
+#[derive(Resource, Debug, Default)]
+pub struct Trajectory {
+    /// starts at the player, followed by every bounce along the way.
+    /// empty while the player isn't in bullet time.
+    pub points: Vec<Vec2>,
+    /// hazards the path passes close to.
+    pub hazards: Vec<Entity>,
+}
+
+/// one of the dashes the trajectory is drawn with, the number is its position along the path.
+#[derive(Component)]
+pub struct TrajectoryDash(usize);
+
+/// the circles drawn around the hazards along the trajectory.
+#[derive(Component)]
+pub struct HazardMarkers;
+
+fn setup_trajectory_preview(mut commands: Commands) {
+    let shape = || ShapeBundle {
+        spatial: SpatialBundle {
+            transform: Transform::from_xyz(0.0, 0.0, 5.0),
+            ..default()
+        },
+        ..default()
+    };
+    for i in 0..DASHES {
+        commands.spawn((shape(), Stroke::new(Color::RED, 2.0), TrajectoryDash(i)));
+    }
+    commands.spawn((shape(), Stroke::new(Color::ORANGE, 2.0), HazardMarkers));
+}
+
+/// the path a ball of `radius` takes from `start` along `dir`, bouncing off of walls the same
+/// way the player does. the path ends at the wall after `bounces` bounces, once it's `max_length`
+/// long, or when it doesn't hit any more walls.
+pub fn predict_path(
+    rapier_context: &RapierContext,
+    start: Vec2,
+    dir: Vec2,
+    radius: f32,
+    bounces: usize,
+    max_length: f32,
+) -> Vec<Vec2> {
+    let mut points = vec![start];
+    let (mut pos, mut dir) = (start, dir);
+    let mut length = 0.0;
+    for _ in 0..=bounces {
+        let bounce = rapier_context
+            .cast_shape(
+                pos,
+                0.0,
+                dir.normalize(),
+                &Collider::ball(radius),
+                max_length - length,
+                false,
+                walls(),
+            )
+            .and_then(|(_, hit)| bounce_off_hit(rapier_context, pos, dir, &hit));
+        let Some(bounce) = bounce else {
+            points.push(pos + dir.normalize() * (max_length - length));
+            break;
+        };
+        length += pos.distance(bounce.pt);
+        (pos, dir) = (bounce.pt, bounce.dir);
+        points.push(pos);
+    }
+    points
+}
+
+pub fn predict_trajectory(
+    rapier_context: Res<RapierContext>,
+    mut trajectory: ResMut<Trajectory>,
+    player: Query<(&Transform, &Player)>,
+    hazards: Query<(Entity, &Transform, &Hazard)>,
+) {
+    trajectory.points.clear();
+    trajectory.hazards.clear();
+    let Ok((transform, player)) = player.get_single() else {
+        return;
+    };
+    let (true, Some(bounce)) = (player.bullet_time, &player.bounce) else {
+        return;
+    };
+    let points = predict_path(
+        &rapier_context,
+        bounce.pt,
+        bounce.dir,
+        player.radius,
+        PREVIEW_BOUNCES,
+        PREVIEW_LENGTH,
+    );
+    trajectory.points.push(transform.translation.truncate());
+    trajectory.points.extend(points);
+
+    for (entity, transform, hazard) in &hazards {
+        let pos = transform.translation.truncate();
+        let reach = player.radius + hazard.radius + HAZARD_WARNING_DIST;
+        if trajectory
+            .points
+            .windows(2)
+            .any(|w| distance_to_segment(pos, w[0], w[1]) < reach)
+        {
+            trajectory.hazards.push(entity);
+        }
+    }
+}
+
+/// draws the trajectory with dashes that fade with the distance from the player,
+/// and circles the hazards along it.
+pub fn draw_trajectory(
+    trajectory: Res<Trajectory>,
+    hazards: Query<(&Transform, &Hazard)>,
+    mut dashes: Query<(&TrajectoryDash, &mut Path, &mut Stroke)>,
+    mut markers: Query<&mut Path, (With<HazardMarkers>, Without<TrajectoryDash>)>,
+) {
+    for (dash, mut path, mut stroke) in &mut dashes {
+        let start = dash.0 as f32 * (DASH_LENGTH + DASH_GAP);
+        let points = sub_path(&trajectory.points, start, start + DASH_LENGTH);
+        let mut path_builder = PathBuilder::new();
+        if let Some((first, rest)) = points.split_first() {
+            path_builder.move_to(*first);
+            for point in rest {
+                path_builder.line_to(*point);
+            }
+        }
+        *path = path_builder.build();
+        stroke.color = Color::RED.with_a(1.0 - start / PREVIEW_LENGTH);
+    }
+
+    let Ok(mut markers) = markers.get_single_mut() else {
+        return;
+    };
+    let mut builder = GeometryBuilder::new();
+    for (transform, hazard) in trajectory
+        .hazards
+        .iter()
+        .filter_map(|&e| hazards.get(e).ok())
+    {
+        builder = builder.add(&shapes::Circle {
+            radius: hazard.radius + 6.0,
+            center: transform.translation.truncate(),
+        });
+    }
+    *markers = builder.build();
+}
+
+/// the part of the polyline `points` that lies between the distances `from` and `to` along it.
+fn sub_path(points: &[Vec2], from: f32, to: f32) -> Vec<Vec2> {
+    let mut sub = vec![];
+    let mut dist = 0.0;
+    for w in points.windows(2) {
+        let len = w[0].distance(w[1]);
+        if dist + len > from && len > 0.0 {
+            let dir = (w[1] - w[0]) / len;
+            if sub.is_empty() {
+                sub.push(w[0] + dir * (from - dist).max(0.0));
+            }
+            sub.push(w[0] + dir * (to - dist).min(len));
+        }
+        dist += len;
+        if dist >= to {
+            break;
+        }
+    }
+    sub
+}
+
+fn distance_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab == Vec2::ZERO {
+        0.0
+    } else {
+        ((p - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    };
+    p.distance(a + ab * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_path_goes_around_corners() {
+        let points = [Vec2::ZERO, Vec2::new(10.0, 0.0), Vec2::new(10.0, 10.0)];
+        assert_eq!(
+            sub_path(&points, 5.0, 15.0),
+            vec![
+                Vec2::new(5.0, 0.0),
+                Vec2::new(10.0, 0.0),
+                Vec2::new(10.0, 5.0)
+            ]
+        );
+        assert_eq!(
+            sub_path(&points, 12.0, 30.0),
+            vec![Vec2::new(10.0, 2.0), Vec2::new(10.0, 10.0)]
+        );
+        assert!(sub_path(&points, 25.0, 30.0).is_empty());
+    }
+}
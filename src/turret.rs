@@ -1,56 +1,350 @@
 use bevy::{
     math::{Vec2, Vec3},
     prelude::*,
-    render::mesh::Mesh,
+    render::{
+        mesh::{Indices, Mesh},
+        render_resource::PrimitiveTopology,
+    },
     sprite::MaterialMesh2dBundle,
 };
+use bevy_rapier2d::{
+    geometry::{Collider, Sensor},
+    pipeline::QueryFilter,
+    plugin::RapierContext,
+};
+use itertools::Itertools;
 
 use crate::{
-    bullet::{bullet_mesh, Bullet},
+    archetype::{Archetypes, FirePattern},
+    bullet::BulletSpawner,
+    health::Dead,
+    level_gen::markers::{DEFAULT_TURRET_ACCURACY, DEFAULT_TURRET_ARCHETYPE, DEFAULT_TURRET_LEAD},
     player::Player,
+    fixed_step::Interpolated,
+    walls, GameSet, Hazard,
 };
 
+/// health turrets start with.
+pub const TURRET_HEALTH: f32 = 2.0;
+/// damage the player does to a turret by running into it.
+pub const RAM_DAMAGE: f32 = 1.0;
+/// extra damage for every bounce since the last bullet time, as a fraction of `RAM_DAMAGE`.
+pub const RAM_BONUS_PER_BOUNCE: f32 = 0.5;
+/// how far off from its target a turret can be pointing and still fire, in radians.
+const AIM_TOLERANCE: f32 = 0.1;
+/// how far to either side of where it's pointing a turret with an accuracy of 0 fires, in radians.
+const MAX_SPREAD: f32 = 0.5;
+
+/// turrets, which aim at the player and fire bullets at it.
+pub struct TurretPlugin;
+
+impl Plugin for TurretPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Archetypes>()
+            .add_event::<TurretDestroyed>()
+            .configure_sets(FixedUpdate, GameSet::Turrets.after(GameSet::Player))
+            .add_systems(
+                FixedUpdate,
+                (turret_system, ram_turrets)
+                    .chain()
+                    .in_set(GameSet::Turrets),
+            )
+            .add_systems(Update, check_archetypes);
+    }
+}
+
 #[derive(Component, Debug)]
 pub struct Turret {
     pub acc: f32,
     pub fire_rate: f32,
+    /// how fast the turret turns towards its target, in radians per second.
     pub rot_speed : f32,
+    /// 1 fires exactly where the turret is pointing, 0 spreads the shots by `MAX_SPREAD`.
+    pub accuracy: f32,
+    /// how much the turret leads the player: 0 aims at where the player is,
+    /// 1 at where a bullet would meet it if it keeps going the same way.
+    pub lead: f32,
+    /// number of times the turret fired, which picks the spread of the next shot.
+    pub shots: u32,
+    /// shots left in the burst being fired, for turrets that fire in bursts.
+    pub burst_left: u32,
+    /// the name of the archetype of the turret, which decides what it fires.
+    pub archetype: String,
+    pub health: f32,
+    /// whether the player was touching the turret in the last step,
+    /// so running through a turret only hurts it once.
+    pub touching_player: bool,
+}
+
+impl Turret {
+    pub fn new(fire_rate: f32, rot_speed: f32) -> Self {
+        Self {
+            acc: 0.0,
+            fire_rate,
+            rot_speed,
+            accuracy: DEFAULT_TURRET_ACCURACY,
+            lead: DEFAULT_TURRET_LEAD,
+            shots: 0,
+            burst_left: 0,
+            archetype: DEFAULT_TURRET_ARCHETYPE.to_string(),
+            health: TURRET_HEALTH,
+            touching_player: false,
+        }
+    }
+
+    /// angle to fire the next shot at, relative to where the turret is pointing.
+    /// the shots are spread out evenly but in no obvious order, and the same way every run.
+    fn next_spread(&mut self) -> f32 {
+        let golden_ratio = 0.618_034;
+        let spread = (1.0 - self.accuracy.clamp(0.0, 1.0)) * MAX_SPREAD;
+        let offset = (self.shots as f32 * golden_ratio).fract() * 2.0 - 1.0;
+        self.shots = self.shots.wrapping_add(1);
+        offset * spread
+    }
+}
+
+/// square base with a barrel pointing along the x axis,
+/// which is the direction the turret fires in.
+pub fn turret_mesh() -> Mesh {
+    let scale = 10.0;
+    let vertices = vec![
+        (-1.0, -1.0),
+        (1.0, -1.0),
+        (1.0, 1.0),
+        (-1.0, 1.0),
+        (1.0, -0.3),
+        (2.0, -0.3),
+        (2.0, 0.3),
+        (1.0, 0.3),
+    ]
+    .into_iter()
+    .map(|v| v.into())
+    .map(|v: Vec2| v * scale)
+    .map(|v| Vec3::new(v.x, v.y, 0.0))
+    .collect_vec();
+
+    let indices = vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7];
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+    mesh
+}
+
+pub fn spawn_turret(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    pos: Vec2,
+    turret: Turret,
+) -> Entity {
+    let transform = Transform::from_translation(pos.extend(0.5));
+    commands
+        .spawn((
+            MaterialMesh2dBundle {
+                mesh: meshes.add(turret_mesh()).into(),
+                material: materials.add(ColorMaterial::from(Color::MAROON)),
+                transform,
+                ..default()
+            },
+            turret,
+            Hazard { radius: 10.0 },
+            Collider::cuboid(10.0, 10.0),
+            Sensor,
+            Interpolated::new(transform),
+        ))
+        .id()
 }
 
 pub fn turret_system(
-    time: Res<Time<Virtual>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    time: Res<Time<Fixed>>,
+    rapier_context: Res<RapierContext>,
+    archetypes: Res<Archetypes>,
     mut turrets: Query<(&mut Transform, &mut Turret)>,
-    mut player: Query<&mut Transform, (With<Player>, Without<Turret>)>,
-    mut commands: Commands,
+    player: Query<(&Transform, &Player), Without<Turret>>,
+    mut bullets: BulletSpawner,
 ) {
-    let player = player.single_mut();
-    turrets.for_each_mut(|(mut turret_transform, mut turret)| {
-        let diff: Vec2 = (player.translation - turret_transform.translation)
-            .xy()
-            .into();
-        turret_transform.rotation =
-            Quat::from_axis_angle(Vec3::new(0., 0., 1.), diff.y.atan2(diff.x));
+    let Ok((player_transform, player)) = player.get_single() else {
+        return;
+    };
+    // hold fire while the player is dead.
+    if player.health <= 0.0 {
+        return;
+    }
+    let player_pos = player_transform.translation.truncate();
+    let player_vel = player.dir.normalize_or_zero() * player.speed;
+    for (mut turret_transform, mut turret) in &mut turrets {
+        // unknown archetypes are warned about when the turret is spawned.
+        let Some(archetype) = archetypes.turret(&turret.archetype) else {
+            continue;
+        };
+        let pos = turret_transform.translation.truncate();
+        let speed = archetype.bullet.speed;
+        let target = lead_target(pos, player_pos, player_vel * turret.lead, speed);
+        if target == pos {
+            continue;
+        }
+        let facing = (turret_transform.rotation * Vec3::X).truncate();
+        let angle = facing.angle_between(target - pos);
+        let max_turn = turret.rot_speed * time.delta_seconds();
+        let turn = angle.clamp(-max_turn, max_turn);
+        turret_transform.rotate_z(turn);
+
         turret.acc += time.delta_seconds();
-        if turret.acc > turret.fire_rate {
-            turret.acc = 0.0;
-            let bullet = Bullet {
-                dmg: 0.1,
-                dir: diff.normalize(),
-                speed: 200.0,
-                radius: 6.,
+        let wait = match archetype.pattern {
+            FirePattern::Burst { interval, .. } if turret.burst_left > 0 => interval,
+            _ => turret.fire_rate,
+        };
+        if turret.acc <= wait
+            || (archetype.pattern.is_aimed() && (angle - turn).abs() > AIM_TOLERANCE)
+            || !line_of_sight(&rapier_context, pos, player_pos)
+        {
+            continue;
+        }
+        turret.acc = 0.0;
+        if let FirePattern::Burst { shots, .. } = archetype.pattern {
+            turret.burst_left = match turret.burst_left {
+                0 => shots.saturating_sub(1),
+                left => left - 1,
             };
-            let mesh = bullet_mesh();
-            commands.spawn((
-                MaterialMesh2dBundle {
-                    mesh: meshes.add(mesh).into(),
-                    material: materials.add(ColorMaterial::from(Color::RED)),
-                    transform: turret_transform.clone(),
-                    ..default()
-                },
-                bullet,
-            ));
-        }
-    });
+        }
+        let volley = turret.shots;
+        // patterns that don't wait for the turret to face the player start at the player instead.
+        let start = if archetype.pattern.is_aimed() {
+            (turret_transform.rotation * Vec3::X).truncate()
+        } else {
+            (target - pos).normalize()
+        };
+        let aim = Vec2::from_angle(turret.next_spread()).rotate(start);
+        for dir in archetype.pattern.directions(aim, volley) {
+            bullets.spawn(
+                &turret.archetype,
+                &archetype.bullet,
+                turret_transform.translation,
+                dir,
+            );
+        }
+    }
+}
+
+/// warn about turrets with archetypes that don't exist, which never fire.
+pub fn check_archetypes(archetypes: Res<Archetypes>, turrets: Query<&Turret, Added<Turret>>) {
+    for turret in &turrets {
+        if archetypes.turret(&turret.archetype).is_none() {
+            warn!("unknown turret archetype '{}'", turret.archetype);
+        }
+    }
+}
+
+/// whether there are no walls between `from` and `to`.
+pub fn line_of_sight(rapier_context: &RapierContext, from: Vec2, to: Vec2) -> bool {
+    let Some(dir) = (to - from).try_normalize() else {
+        return true;
+    };
+    rapier_context
+        .cast_ray(from, dir, from.distance(to), true, walls())
+        .is_none()
+}
+
+/// where to aim from `from` to hit a target at `target` moving with `vel`, with a bullet
+/// going `speed`. aims straight at the target if the bullet can't catch up with it.
+pub fn lead_target(from: Vec2, target: Vec2, vel: Vec2, speed: f32) -> Vec2 {
+    // solve |target + vel * t - from| = speed * t for the earliest time t > 0.
+    let diff = target - from;
+    let a = vel.length_squared() - speed * speed;
+    let b = 2.0 * diff.dot(vel);
+    let c = diff.length_squared();
+    let t = if a.abs() < f32::EPSILON {
+        Some(-c / b).filter(|_| b < 0.0)
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        (discriminant >= 0.0)
+            .then(|| {
+                let root = discriminant.sqrt();
+                [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+            })
+            .and_then(|ts| ts.into_iter().filter(|t| *t > 0.0).reduce(f32::min))
+    };
+    t.map_or(target, |t| target + vel * t)
+}
+
+/// a turret was destroyed by the player at `pos`. the turret entity is despawned.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct TurretDestroyed {
+    pub turret: Entity,
+    pub pos: Vec2,
+}
+
+/// damage the player does to a turret it runs into. the player hits harder
+/// the more it has bounced around since the last bullet time.
+pub fn ram_damage(player: &Player) -> f32 {
+    RAM_DAMAGE * (1.0 + RAM_BONUS_PER_BOUNCE * player.bounces_since_bullet_time as f32)
+}
+
+/// damage turrets the player runs into, once per contact, and destroy them once they're
+/// out of health. the player's ball is swept over its whole step, so a turret the player
+/// only passes by during the step is hit as well.
+pub fn ram_turrets(
+    mut commands: Commands,
+    rapier_context: Res<RapierContext>,
+    mut destroyed: EventWriter<TurretDestroyed>,
+    player: Query<(&Transform, &Interpolated, &Player), Without<Dead>>,
+    mut turrets: Query<(Entity, &Transform, &mut Turret)>,
+) {
+    let Ok((player_transform, interpolated, player)) = player.get_single() else {
+        return;
+    };
+    // the transform of the last step hasn't been recorded yet, so this step started at `current`.
+    let from = interpolated.current.translation.truncate();
+    let to = player_transform.translation.truncate();
+    let ball = Collider::ball(player.radius);
+    let mut touching = vec![];
+    // a cast only finds the first sensor it hits, so cast again without it until none are left.
+    loop {
+        let hit = rapier_context.cast_shape(
+            from,
+            0.0,
+            to - from,
+            &ball,
+            1.0,
+            true,
+            QueryFilter::new()
+                .exclude_solids()
+                .predicate(&|entity| !touching.contains(&entity)),
+        );
+        let Some((entity, _)) = hit else {
+            break;
+        };
+        touching.push(entity);
+    }
+    for (entity, transform, mut turret) in &mut turrets {
+        let was_touching = turret.touching_player;
+        turret.touching_player = touching.contains(&entity);
+        if !turret.touching_player || was_touching {
+            continue;
+        }
+        turret.health -= ram_damage(player);
+        if turret.health <= 0.0 {
+            commands.entity(entity).despawn();
+            destroyed.send(TurretDestroyed {
+                turret: entity,
+                pos: transform.translation.truncate(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leading_meets_the_target() {
+        let (from, target, vel) = (Vec2::ZERO, Vec2::new(100.0, 0.0), Vec2::new(0.0, 50.0));
+        let aim = lead_target(from, target, vel, 200.0);
+        let t = aim.length() / 200.0;
+        assert!(aim.distance(target + vel * t) < 0.01, "aimed at {}", aim);
+        // too fast to catch up with.
+        let vel = Vec2::new(300.0, 0.0);
+        assert_eq!(lead_target(from, target, vel, 200.0), target);
+    }
 }
@@ -0,0 +1,1012 @@
+use std::fs;
+
+use bevy::{ecs::event::ManualEventReader, prelude::*, sprite::Mesh2dHandle};
+use bevy_rapier2d::geometry::Collider;
+use trajectory::{
+    archetype::{Archetypes, BulletArchetype, FirePattern, TurretArchetype},
+    bullet::{Accelerate, Bullet, BulletPool, Homing, Wave, BULLET_CULL_DISTANCE, BULLET_LIFETIME},
+    fixed_step::{Interpolated, StepCount, TIMESTEP},
+    headless::Headless,
+    health::{Dead, PlayerDamaged, PlayerDied, RESPAWN_DELAY},
+    level::{Level, LevelConfig, LevelSource, OnDeath},
+    turret::{Turret, TurretDestroyed, RAM_DAMAGE, TURRET_HEALTH},
+    level_gen::markers::{DEFAULT_TURRET_ARCHETYPE, DEFAULT_TURRET_ROT_SPEED},
+    player::{BounceEvent, Player},
+    replay::{Replay, ReplayInput, ReplayMode, ReplayPlugin},
+    trajectory::{Trajectory, PREVIEW_BOUNCES},
+    GameSet, Hazard,
+};
+
+/// closed room with the player spawn in the middle.
+const ARENA: &str = "\
+################
+#..............#
+#..............#
+#..............#
+#..............#
+#......P.......#
+#..............#
+#..............#
+#..............#
+#..............#
+#..............#
+################
+";
+
+fn arena_config() -> LevelConfig {
+    LevelConfig {
+        source: LevelSource::Ascii(ARENA.to_string()),
+        ..Default::default()
+    }
+}
+
+fn arena() -> Headless {
+    Headless::new(arena_config())
+}
+
+/// steps until exactly `steps` fixed steps have run, then returns the state of the player.
+/// frames have to be at most one step long, so the step count can't skip past `steps`.
+fn player_after_steps(game: &mut Headless, steps: u64) -> (Vec3, Vec2, usize) {
+    assert!(game.fixed_steps() <= steps);
+    let done = game.step_until(100_000, |world| world.resource::<StepCount>().0 == steps);
+    assert!(done, "only ran {} steps", game.fixed_steps());
+    let (interpolated, player) = game
+        .app
+        .world
+        .query::<(&Interpolated, &Player)>()
+        .single(&game.app.world);
+    (
+        interpolated.current.translation,
+        player.dir,
+        player.bounces_since_bullet_time,
+    )
+}
+
+/// move the player to `pos` between two steps.
+fn teleport_player(game: &mut Headless, pos: Vec2) {
+    let (mut transform, mut interpolated) = game
+        .app
+        .world
+        .query_filtered::<(&mut Transform, &mut Interpolated), With<Player>>()
+        .single_mut(&mut game.app.world);
+    transform.translation = pos.extend(transform.translation.z);
+    interpolated.teleport(*transform);
+}
+
+/// steps once, and returns the bounce events sent during the step.
+fn step_and_read_bounces(
+    game: &mut Headless,
+    reader: &mut ManualEventReader<BounceEvent>,
+) -> Vec<BounceEvent> {
+    game.step(1);
+    reader
+        .read(game.app.world.resource::<Events<BounceEvent>>())
+        .copied()
+        .collect()
+}
+
+fn arena_bounds() -> (Vec2, Vec2) {
+    let spacing = LevelConfig::default().node_spacing as f32;
+    (
+        Vec2::new(0.0, -11.0 * spacing),
+        Vec2::new(15.0 * spacing, 0.0),
+    )
+}
+
+fn in_bullet_time(world: &mut World) -> bool {
+    world
+        .query::<&Player>()
+        .get_single(world)
+        .is_ok_and(|player| player.bullet_time)
+}
+
+#[test]
+fn player_bounces_around_inside_of_the_arena() {
+    let mut game = arena();
+    let (min, max) = arena_bounds();
+
+    let start_dir = Player::new().dir;
+    let mut bounced = false;
+    for _ in 0..300 {
+        game.step(1);
+        let (transform, player) = game.player().expect("player should be spawned");
+        let pos = transform.translation.truncate();
+        assert!(
+            pos.cmpge(min).all() && pos.cmple(max).all(),
+            "player escaped the arena at {}",
+            pos
+        );
+        bounced |= player.dir != start_dir;
+    }
+    assert!(bounced, "player never bounced off of a wall");
+}
+
+#[test]
+fn player_inside_of_a_wall_is_pushed_out() {
+    let mut game = arena();
+    game.step(1);
+    let mut reader = ManualEventReader::default();
+    // the left wall is made of the first column of nodes.
+    let in_wall = Vec2::new(2.0, -100.0);
+    teleport_player(&mut game, in_wall);
+    let events = step_and_read_bounces(&mut game, &mut reader);
+    let Some(&BounceEvent::InsideWall { from, to }) = events.first() else {
+        panic!("expected the player to be pushed out of the wall, got {:?}", events);
+    };
+    assert_eq!(from, in_wall);
+    assert!(to.x > from.x, "pushed the wrong way, to {}", to);
+
+    for _ in 0..300 {
+        let events = step_and_read_bounces(&mut game, &mut reader);
+        assert!(events.is_empty(), "player got stuck: {:?}", events);
+    }
+}
+
+#[test]
+fn player_that_left_the_level_is_brought_back() {
+    let mut game = arena();
+    game.step(1);
+    let mut reader = ManualEventReader::default();
+    teleport_player(&mut game, Vec2::new(-500.0, 300.0));
+    let events = step_and_read_bounces(&mut game, &mut reader);
+    assert!(
+        matches!(
+            events.as_slice(),
+            [BounceEvent::OutOfBounds { .. }, BounceEvent::InsideWall { .. }]
+        ),
+        "{:?}",
+        events
+    );
+
+    let (min, max) = arena_bounds();
+    for _ in 0..300 {
+        game.step(1);
+        let pos = game.player().unwrap().0.translation.truncate();
+        assert!(pos.cmpge(min).all() && pos.cmple(max).all(), "player at {}", pos);
+    }
+}
+
+#[test]
+fn player_keeps_moving_without_walls() {
+    let mut game = arena();
+    game.step(1);
+    let walls: Vec<Entity> = game
+        .app
+        .world
+        .query_filtered::<Entity, With<Collider>>()
+        .iter(&game.app.world)
+        .collect();
+    for entity in walls {
+        game.app.world.entity_mut(entity).remove::<Collider>();
+    }
+    // physics only finds out the colliders are gone at the end of the next frame.
+    game.step(1);
+    let mut reader = ManualEventReader::default();
+    let mut events = vec![];
+    let mut positions = vec![];
+    for _ in 0..600 {
+        events.extend(step_and_read_bounces(&mut game, &mut reader));
+        positions.push(game.player().unwrap().0.translation);
+    }
+    // the player still bounces off of the wall it was heading for before it leaves,
+    // and then bounces off of the edges of the level.
+    assert!(
+        matches!(events.first(), Some(BounceEvent::NoWallAhead { .. })),
+        "{:?}",
+        events
+    );
+    assert!(events[1..]
+        .iter()
+        .all(|event| matches!(event, BounceEvent::OutOfBounds { .. })));
+    assert!(
+        positions.windows(2).all(|w| w[0] != w[1]),
+        "player got stuck"
+    );
+}
+
+/// spawns a bullet that doesn't move on top of the player,
+/// big enough that the player can't get away from it in a step.
+fn shoot_player(game: &mut Headless, dmg: f32) -> Entity {
+    let pos = game.player().unwrap().0.translation;
+    game.app
+        .world
+        .spawn((
+            Transform::from_translation(pos),
+            Bullet {
+                dmg,
+                dir: Vec2::X,
+                speed: 0.0,
+                radius: 40.0,
+                bounces_left: 0,
+                lifetime: BULLET_LIFETIME,
+            },
+        ))
+        .id()
+}
+
+fn kill_player(game: &mut Headless) {
+    game.player_mut().unwrap().health = 0.0;
+}
+
+fn player_is_dead(world: &mut World) -> bool {
+    world
+        .query_filtered::<(), (With<Player>, With<Dead>)>()
+        .get_single(world)
+        .is_ok()
+}
+
+#[test]
+fn bullets_hurt_the_player_unless_it_was_just_hurt() {
+    let mut game = arena();
+    game.step(1);
+    let mut reader = ManualEventReader::<PlayerDamaged>::default();
+    let first = shoot_player(&mut game, 0.5);
+    game.step(1);
+    let events: Vec<_> = reader
+        .read(game.app.world.resource::<Events<PlayerDamaged>>())
+        .copied()
+        .collect();
+    assert_eq!(
+        events,
+        vec![PlayerDamaged {
+            amount: 0.5,
+            health: 1.5
+        }]
+    );
+    assert!(game.app.world.get::<Bullet>(first).is_none());
+
+    // bullets go through the player while it's invulnerable.
+    let second = shoot_player(&mut game, 0.5);
+    game.step(1);
+    assert!(game.app.world.get::<Bullet>(second).is_some());
+    assert_eq!(game.player().unwrap().1.health, 1.5);
+    game.app.world.despawn(second);
+
+    // the player might be in bullet time, where steps are few and far between.
+    assert!(game.step_until(100_000, |world| {
+        world.query::<&Player>().single(world).invulnerable == 0.0
+    }));
+    shoot_player(&mut game, 0.5);
+    assert!(game.step_until(1000, |world| {
+        world.query::<&Player>().single(world).health == 1.0
+    }));
+}
+
+#[test]
+fn player_respawns_after_dying() {
+    let mut game = arena();
+    game.step(30);
+    let spawn = game.app.world.resource::<Level>().player_spawn();
+    let mut reader = ManualEventReader::<PlayerDied>::default();
+    kill_player(&mut game);
+    game.step(1);
+    assert!(player_is_dead(&mut game.app.world));
+    assert_eq!(
+        reader
+            .read(game.app.world.resource::<Events<PlayerDied>>())
+            .count(),
+        1
+    );
+
+    // the player doesn't move while it's dead.
+    let pos = game
+        .app
+        .world
+        .query_filtered::<&Interpolated, With<Player>>()
+        .single(&game.app.world)
+        .current
+        .translation;
+    let steps = game.fixed_steps() + 10;
+    assert_eq!(player_after_steps(&mut game, steps).0, pos);
+
+    let respawn_steps = (RESPAWN_DELAY / TIMESTEP.as_secs_f32()) as usize + 1;
+    assert!(game.step_until(respawn_steps, |world| !player_is_dead(world)));
+    let (transform, player) = game.player().unwrap();
+    assert_eq!(transform.translation.truncate(), spawn);
+    assert_eq!(player.health, player.max_health);
+    assert!(player.invulnerable > 0.0);
+}
+
+#[test]
+fn restarting_puts_the_level_back() {
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(ARENA.replacen("#.....", "#T....", 1)),
+        on_death: OnDeath::Restart,
+        ..Default::default()
+    });
+    game.step(1);
+    let player = game
+        .app
+        .world
+        .query_filtered::<Entity, With<Player>>()
+        .single(&game.app.world);
+    let turrets = |game: &mut Headless| {
+        game.app
+            .world
+            .query_filtered::<Entity, With<Turret>>()
+            .iter(&game.app.world)
+            .collect::<Vec<_>>()
+    };
+    let old_turrets = turrets(&mut game);
+    assert_eq!(old_turrets.len(), 1);
+
+    kill_player(&mut game);
+    let respawn_steps = (RESPAWN_DELAY / TIMESTEP.as_secs_f32()) as usize + 2;
+    assert!(game.step_until(respawn_steps, |world| world.get_entity(player).is_none()));
+    let new_turrets = turrets(&mut game);
+    assert_eq!(new_turrets.len(), 1);
+    assert_ne!(new_turrets, old_turrets);
+    assert!(!player_is_dead(&mut game.app.world));
+    assert!(game
+        .app
+        .world
+        .query::<&Bullet>()
+        .iter(&game.app.world)
+        .next()
+        .is_none());
+}
+
+#[test]
+fn running_into_turrets_damages_and_destroys_them() {
+    // a turret right below the spawn, away from the walls.
+    let level = ARENA
+        .lines()
+        .enumerate()
+        .map(|(y, line)| if y == 8 { "#......T.......#" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(level),
+        ..Default::default()
+    });
+    game.step(2);
+    let (turret, turret_pos) = game
+        .app
+        .world
+        .query_filtered::<(Entity, &Transform), With<Turret>>()
+        .single(&game.app.world);
+    let turret_pos = turret_pos.translation.truncate();
+    let turret_health =
+        |game: &mut Headless| game.app.world.get::<Turret>(turret).map(|t| t.health);
+    let ram = |game: &mut Headless, bounces: usize| {
+        game.player_mut().unwrap().bounces_since_bullet_time = bounces;
+        teleport_player(game, turret_pos);
+        game.step_fixed(1);
+    };
+
+    let mut reader = ManualEventReader::<TurretDestroyed>::default();
+    ram(&mut game, 0);
+    assert_eq!(turret_health(&mut game), Some(TURRET_HEALTH - RAM_DAMAGE));
+    // staying in contact doesn't hurt it again.
+    game.step(1);
+    assert_eq!(turret_health(&mut game), Some(TURRET_HEALTH - RAM_DAMAGE));
+
+    // move away, then come back after bouncing around, which hits harder.
+    game.step_fixed(10);
+    ram(&mut game, 2);
+    game.step(1);
+    assert!(turret_health(&mut game).is_none());
+    let events = game.app.world.resource::<Events<TurretDestroyed>>();
+    assert_eq!(
+        reader.read(events).copied().collect::<Vec<_>>(),
+        vec![TurretDestroyed {
+            turret,
+            pos: turret_pos
+        }]
+    );
+}
+
+#[test]
+fn passing_a_turret_corner_within_a_step_rams_it() {
+    let level = ARENA
+        .lines()
+        .enumerate()
+        .map(|(y, line)| if y == 8 { "#......T.......#" } else { line })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(level),
+        ..Default::default()
+    });
+    game.step(2);
+    // keep the turret square with the axes, so its corner stays put.
+    let (mut transform, mut turret) = game
+        .app
+        .world
+        .query::<(&mut Transform, &mut Turret)>()
+        .single_mut(&mut game.app.world);
+    turret.rot_speed = 0.0;
+    transform.rotation = Quat::IDENTITY;
+    let turret_pos = transform.translation.truncate();
+    game.step(1);
+
+    // move diagonally past the top right corner of the turret, 4 units away from touching it
+    // at the halfway point, so the player is clear of it at both ends of the step.
+    let dir = Vec2::new(1.0, -1.0).normalize();
+    let half_step = game.player().unwrap().1.speed * TIMESTEP.as_secs_f32() / 2.0;
+    let closest = turret_pos + Vec2::splat(10.0) + Vec2::splat(4.0 / std::f32::consts::SQRT_2);
+    teleport_player(&mut game, closest - dir * half_step);
+    let mut player = game.player_mut().unwrap();
+    player.dir = dir;
+    player.bounce = None;
+    // the first step only works out where the player will bounce.
+    game.step_fixed(2);
+
+    let pos = game
+        .app
+        .world
+        .query_filtered::<&Interpolated, With<Player>>()
+        .single(&game.app.world)
+        .current
+        .translation
+        .truncate();
+    assert!(pos.distance(closest + dir * half_step) < 0.01, "player at {}", pos);
+    let turret = game.app.world.query::<&Turret>().single(&game.app.world);
+    assert_eq!(turret.health, TURRET_HEALTH - RAM_DAMAGE);
+}
+
+#[test]
+fn turrets_turn_slowly_and_only_fire_when_they_can_see_the_player() {
+    // two rooms split by a wall with a gap in it, which the turret is lined up with.
+    let level = "\
+################
+#......#.......#
+#......#.......#
+#......#.......#
+#..P.........T.#
+#......#.......#
+#......#.......#
+#......#.......#
+################
+";
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(level.to_string()),
+        ..Default::default()
+    });
+    game.step(1);
+    let turret_angle = |game: &mut Headless| {
+        let transform = game
+            .app
+            .world
+            .query_filtered::<&Transform, With<Turret>>()
+            .single(&game.app.world);
+        (transform.rotation * Vec3::X).truncate()
+    };
+    let bullets = |game: &mut Headless| {
+        game.app
+            .world
+            .query::<&Bullet>()
+            .iter(&game.app.world)
+            .count()
+    };
+    // keep the player on the far side of the wall from the turret.
+    let hidden = Vec2::new(60.0, -40.0);
+    let mut seen_bullets = 0;
+    for _ in 0..200 {
+        let before = turret_angle(&mut game);
+        teleport_player(&mut game, hidden);
+        game.step_fixed(1);
+        let turned = before.angle_between(turret_angle(&mut game)).abs();
+        assert!(turned <= DEFAULT_TURRET_ROT_SPEED * TIMESTEP.as_secs_f32() + 1e-4);
+        seen_bullets = seen_bullets.max(bullets(&mut game));
+    }
+    assert_eq!(seen_bullets, 0);
+
+    // through the gap, the turret can see the player.
+    let visible = Vec2::new(60.0, -80.0);
+    for _ in 0..200 {
+        teleport_player(&mut game, visible);
+        game.step_fixed(1);
+        seen_bullets = seen_bullets.max(bullets(&mut game));
+    }
+    assert!(seen_bullets > 0);
+}
+
+#[test]
+fn turrets_fire_what_their_archetype_says() {
+    let mut archetypes = Archetypes::default();
+    archetypes.turrets.insert(
+        "shotgun".to_string(),
+        TurretArchetype {
+            bullet: BulletArchetype {
+                speed: 123.0,
+                dmg: 0.5,
+                radius: 3.0,
+                color: Color::ORANGE,
+                max_bounces: 0,
+                lifetime: BULLET_LIFETIME,
+                homing: None,
+                wave: None,
+                accelerate: None,
+            },
+            pattern: FirePattern::Spread {
+                count: 5,
+                angle: 1.0,
+            },
+        },
+    );
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(format!("{}\nturret 3 3 0.5 10 1 0 shotgun\n", ARENA)),
+        ..Default::default()
+    });
+    game.app.insert_resource(archetypes);
+    let done = game.step_until(100_000, |world| {
+        world.query::<&Bullet>().iter(world).count() > 0
+    });
+    assert!(done);
+    let bullets: Vec<(f32, f32, f32, Vec2)> = game
+        .app
+        .world
+        .query::<&Bullet>()
+        .iter(&game.app.world)
+        .map(|bullet| (bullet.speed, bullet.dmg, bullet.radius, bullet.dir))
+        .collect();
+    assert_eq!(bullets.len(), 5);
+    assert!(bullets
+        .iter()
+        .all(|&(speed, dmg, radius, _)| (speed, dmg, radius) == (123.0, 0.5, 3.0)));
+    let spread = bullets[0].3.angle_between(bullets[4].3).abs();
+    assert!((spread - 1.0).abs() < 1e-3, "spread over {}", spread);
+}
+
+#[test]
+fn rings_start_at_the_player() {
+    let mut archetypes = Archetypes::default();
+    let mut ring = archetypes.turrets[DEFAULT_TURRET_ARCHETYPE].clone();
+    ring.pattern = FirePattern::Ring { count: 3 };
+    archetypes.turrets.insert("ring".to_string(), ring);
+    // the turret never turns, so it keeps facing away from the player.
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(format!("{}\nturret 3 3 0.5 0 1 0 ring\n", ARENA)),
+        ..Default::default()
+    });
+    game.app.insert_resource(archetypes);
+    let done = game.step_until(100_000, |world| {
+        world.query::<&Bullet>().iter(world).count() > 0
+    });
+    assert!(done);
+    let turret_pos = game
+        .app
+        .world
+        .query_filtered::<&Transform, With<Turret>>()
+        .single(&game.app.world)
+        .translation
+        .truncate();
+    let player_pos = game
+        .app
+        .world
+        .query_filtered::<&Interpolated, With<Player>>()
+        .single(&game.app.world)
+        .current
+        .translation
+        .truncate();
+    let to_player = (player_pos - turret_pos).normalize();
+    let dirs: Vec<Vec2> = game
+        .app
+        .world
+        .query::<&Bullet>()
+        .iter(&game.app.world)
+        .map(|bullet| bullet.dir)
+        .collect();
+    assert_eq!(dirs.len(), 3);
+    assert!(
+        dirs.iter().any(|dir| dir.angle_between(to_player).abs() < 1e-3),
+        "fired {:?} with the player towards {}",
+        dirs,
+        to_player
+    );
+}
+
+#[test]
+fn turrets_fire_bursts_one_shot_at_a_time() {
+    let mut archetypes = Archetypes::default();
+    archetypes.turrets.insert(
+        "burst".to_string(),
+        TurretArchetype {
+            pattern: FirePattern::Burst {
+                shots: 3,
+                interval: 0.1,
+            },
+            ..archetypes.turret("default").unwrap().clone()
+        },
+    );
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(format!("{}\nturret 3 3 0.5 10 1 0 burst\n", ARENA)),
+        ..Default::default()
+    });
+    game.app.insert_resource(archetypes);
+    game.step(1);
+    // so the player can't destroy it by running into it.
+    game.turret_mut().unwrap().health = f32::INFINITY;
+    // the step each of the bullets was fired on. removed bullets are reused,
+    // so a bullet is new if it wasn't there last frame.
+    let mut last_frame = vec![];
+    let mut fired = vec![];
+    let done = game.step_until(100_000, |world| {
+        let bullets: Vec<Entity> = world
+            .query_filtered::<Entity, With<Bullet>>()
+            .iter(world)
+            .collect();
+        let new = bullets.iter().filter(|b| !last_frame.contains(*b)).count();
+        assert!(new <= 1, "fired more than one shot at once");
+        if new == 1 {
+            fired.push(world.resource::<StepCount>().0);
+        }
+        last_frame = bullets;
+        fired.len() == 3
+    });
+    assert!(done);
+    // the whole burst is fired before the turret would fire again.
+    let fire_rate = (0.5 / TIMESTEP.as_secs_f32()) as u64;
+    assert!(fired[2] - fired[0] < fire_rate, "{:?}", fired);
+}
+
+#[test]
+fn bullets_ricochet_until_they_run_out_of_bounces() {
+    let mut game = arena();
+    game.step(1);
+    // so the bullet flies through the player.
+    game.player_mut().unwrap().invulnerable = f32::INFINITY;
+    let bullet = game
+        .app
+        .world
+        .spawn((
+            Transform::from_xyz(140.0, -40.0, 0.0),
+            Bullet {
+                dmg: 0.1,
+                dir: Vec2::X,
+                speed: 400.0,
+                radius: 6.0,
+                bounces_left: 1,
+                lifetime: BULLET_LIFETIME,
+            },
+        ))
+        .id();
+    let bounced = game.step_until(100_000, |world| {
+        world.get::<Bullet>(bullet).is_some_and(|b| b.dir.x < 0.0)
+    });
+    assert!(bounced);
+    let bounce = game.app.world.get::<Transform>(bullet).unwrap().translation;
+    assert_eq!(
+        game.app.world.get::<Bullet>(bullet).unwrap().bounces_left,
+        0
+    );
+    assert!(bounce.x > 140.0);
+
+    let mut last = bounce;
+    let broke = game.step_until(100_000, |world| match world.get::<Bullet>(bullet) {
+        Some(_) => {
+            last = world.get::<Transform>(bullet).unwrap().translation;
+            false
+        }
+        None => true,
+    });
+    assert!(broke);
+    // it broke on the wall on the other side.
+    assert!(last.x < 140.0, "broke at {}", last);
+}
+
+#[test]
+fn bullets_home_weave_and_speed_up() {
+    let mut game = arena();
+    game.step(1);
+    game.player_mut().unwrap().invulnerable = f32::INFINITY;
+    let bullet = |dir: Vec2| Bullet {
+        dmg: 0.1,
+        dir,
+        speed: 50.0,
+        radius: 6.0,
+        bounces_left: 0,
+        lifetime: BULLET_LIFETIME,
+    };
+    let start = Vec3::new(140.0, -40.0, 0.0);
+    let homing = game
+        .app
+        .world
+        .spawn((
+            Transform::from_translation(start),
+            Interpolated::new(Transform::from_translation(start)),
+            bullet(Vec2::Y),
+            Homing {
+                turn_rate: 10.0,
+                lifetime: 10.0,
+            },
+            Accelerate {
+                rate: 100.0,
+                min_speed: 0.0,
+                max_speed: 80.0,
+            },
+        ))
+        .id();
+    let weaving = game
+        .app
+        .world
+        .spawn((
+            Transform::from_translation(start),
+            bullet(Vec2::X),
+            Wave {
+                amplitude: 0.5,
+                frequency: 1.0,
+                time: 0.0,
+            },
+        ))
+        .id();
+
+    // half a second later the homing bullet turned around, and hit its top speed.
+    game.step_fixed((0.5 / TIMESTEP.as_secs_f32()) as u64);
+    let current = |game: &Headless, entity| {
+        game.app
+            .world
+            .get::<Interpolated>(entity)
+            .unwrap()
+            .current
+            .translation
+    };
+    let player = game
+        .app
+        .world
+        .query_filtered::<Entity, With<Player>>()
+        .single(&game.app.world);
+    let (player, pos) = (current(&game, player), current(&game, homing));
+    let homing = game.app.world.get::<Bullet>(homing).unwrap();
+    let to_player = (player - pos).truncate();
+    assert!(
+        homing.dir.angle_between(to_player).abs() < 0.1,
+        "{:?}",
+        homing
+    );
+    assert_eq!(homing.speed, 80.0);
+    // the weaving bullet swerves off to the side and comes back.
+    let wave = game.app.world.get::<Wave>(weaving).unwrap();
+    let dir = game.app.world.get::<Bullet>(weaving).unwrap().dir;
+    let expected = 0.5 * (std::f32::consts::TAU * wave.time).sin();
+    assert!((Vec2::X.angle_between(dir) - expected).abs() < 1e-3);
+}
+
+#[test]
+fn bullets_expire_and_are_reused() {
+    let mut archetypes = Archetypes::default();
+    let default = archetypes.turret("default").unwrap().clone();
+    archetypes.turrets.insert(
+        "short".to_string(),
+        TurretArchetype {
+            bullet: BulletArchetype {
+                lifetime: 0.1,
+                ..default.bullet
+            },
+            ..default
+        },
+    );
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Ascii(format!("{}\nturret 3 3 0.2 10 1 0 short\n", ARENA)),
+        ..Default::default()
+    });
+    game.app.insert_resource(archetypes);
+    game.step(1);
+    game.turret_mut().unwrap().health = f32::INFINITY;
+    game.player_mut().unwrap().invulnerable = f32::INFINITY;
+
+    // bullets far away from the player are removed right away.
+    let far = game
+        .app
+        .world
+        .spawn((
+            Transform::from_xyz(BULLET_CULL_DISTANCE * 2.0, 0.0, 0.0),
+            Bullet {
+                dmg: 0.1,
+                dir: Vec2::X,
+                speed: 0.0,
+                radius: 6.0,
+                bounces_left: 0,
+                lifetime: BULLET_LIFETIME,
+            },
+        ))
+        .id();
+    game.step_fixed(1);
+    assert!(game.app.world.get::<Bullet>(far).is_none());
+    assert!(game.app.world.resource::<BulletPool>().0.contains(&far));
+
+    // every shot expires before the next one, so the turret keeps reusing the same bullet,
+    // which is drawn with the same mesh and material every time.
+    let mut shots = vec![];
+    let mut last_frame = None;
+    let done = game.step_until(100_000, |world| {
+        let bullet = world
+            .query_filtered::<(Entity, &Mesh2dHandle, &Handle<ColorMaterial>), With<Bullet>>()
+            .get_single(world)
+            .ok()
+            .map(|(entity, mesh, material)| (entity, mesh.0.id(), material.id()));
+        if let (Some(bullet), None) = (bullet, last_frame) {
+            shots.push(bullet);
+        }
+        last_frame = bullet;
+        shots.len() == 10
+    });
+    assert!(done);
+    assert_eq!(shots[0].0, far);
+    assert!(shots.iter().all(|&shot| shot == shots[0]), "{:?}", shots);
+}
+
+#[test]
+fn clicking_ends_bullet_time() {
+    let mut game = arena();
+    assert!(
+        game.step_until(1200, in_bullet_time),
+        "bullet time never started"
+    );
+    assert_eq!(
+        game.app.world.resource::<Time<Virtual>>().relative_speed(),
+        0.005
+    );
+
+    game.set_mouse(Vec2::new(150.0, -110.0));
+    game.click(MouseButton::Left);
+    game.step(1);
+    assert!(!in_bullet_time(&mut game.app.world));
+    assert_eq!(
+        game.app.world.resource::<Time<Virtual>>().relative_speed(),
+        1.0
+    );
+}
+
+#[test]
+fn trajectory_preview_follows_the_bounces_and_marks_hazards() {
+    let mut game = arena();
+    assert!(
+        game.step_until(1200, in_bullet_time),
+        "bullet time never started"
+    );
+    game.step(1);
+    let points = game.app.world.resource::<Trajectory>().points.clone();
+    // the player, the bounce it's heading for, and the bounces after it.
+    assert_eq!(points.len(), PREVIEW_BOUNCES + 3);
+    let (min, max) = arena_bounds();
+    for point in &points {
+        assert!(point.cmpge(min).all() && point.cmple(max).all(), "{}", point);
+    }
+
+    let near = game
+        .app
+        .world
+        .spawn((
+            Transform::from_translation(points[3].lerp(points[4], 0.5).extend(0.0)),
+            Hazard { radius: 5.0 },
+        ))
+        .id();
+    let far = game
+        .app
+        .world
+        .spawn((
+            Transform::from_xyz(-1000.0, 1000.0, 0.0),
+            Hazard { radius: 5.0 },
+        ))
+        .id();
+    game.step(1);
+    let hazards = &game.app.world.resource::<Trajectory>().hazards;
+    assert!(hazards.contains(&near));
+    assert!(!hazards.contains(&far));
+
+    game.click(MouseButton::Left);
+    game.step(1);
+    assert!(game.app.world.resource::<Trajectory>().points.is_empty());
+}
+
+#[test]
+fn camera_follows_the_player_in_a_generated_level() {
+    let mut game = Headless::new(LevelConfig {
+        source: LevelSource::Dungeon,
+        seed: 1,
+        dimensions: [60, 60],
+        ..Default::default()
+    });
+    game.step(60);
+    let player = game
+        .player()
+        .expect("player should be spawned")
+        .0
+        .translation;
+    let camera = game
+        .app
+        .world
+        .query_filtered::<&Transform, With<Camera>>()
+        .single(&game.app.world)
+        .translation;
+    assert_eq!(player, camera);
+}
+
+#[test]
+fn simulation_doesnt_depend_on_the_frame_rate() {
+    let run = |frame_time| {
+        let mut game = Headless::with_frame_time(arena_config(), frame_time);
+        player_after_steps(&mut game, 400)
+    };
+    assert_eq!(run(TIMESTEP), run(TIMESTEP / 3));
+}
+
+#[test]
+fn replay_reproduces_the_recorded_run() {
+    let path = std::env::temp_dir().join(format!("trajectory-replay-{}.ron", std::process::id()));
+    let mut game = arena();
+    game.app.add_plugins(ReplayPlugin {
+        mode: ReplayMode::Record(path.clone()),
+    });
+    game.step(30);
+    // carve through the right wall, so the player can leave the arena.
+    game.set_mouse(Vec2::new(300.0, -110.0));
+    game.click(MouseButton::Right);
+    assert!(
+        game.step_until(1200, in_bullet_time),
+        "bullet time never started"
+    );
+    game.set_mouse(Vec2::new(40.0, -40.0));
+    game.step(3);
+    game.set_mouse(Vec2::new(250.0, -180.0));
+    game.step(3);
+    game.click(MouseButton::Left);
+    let recorded = player_after_steps(&mut game, 600);
+
+    let replay = Replay::load(&path).expect("replay should be saved while recording");
+    fs::remove_file(&path).unwrap();
+    assert!(replay
+        .inputs
+        .iter()
+        .any(|i| i.input == ReplayInput::Confirm));
+    assert_eq!(replay.edits.len(), 1);
+
+    // no live input is used while replaying, and the frame rate doesn't matter.
+    let mut game = Headless::with_frame_time(replay.level.clone(), TIMESTEP / 2);
+    game.set_mouse(Vec2::ZERO);
+    game.app.add_plugins(ReplayPlugin {
+        mode: ReplayMode::Play(replay),
+    });
+    assert_eq!(player_after_steps(&mut game, 600), recorded);
+}
+
+/// the position of the player after every fixed step.
+#[derive(Resource, Default)]
+struct Track(Vec<Option<Vec3>>);
+
+fn track_player(mut track: ResMut<Track>, player: Query<&Transform, With<Player>>) {
+    track.0.push(player.get_single().ok().map(|t| t.translation));
+}
+
+/// track the player of `game`, which never enters bullet time so every frame runs the same
+/// number of steps.
+fn track_without_bullet_time(game: &mut Headless) {
+    game.app
+        .init_resource::<Track>()
+        .add_systems(FixedUpdate, track_player.after(GameSet::Player));
+    game.step(1);
+    game.player_mut().unwrap().max_bullet_time_dist = 0.0;
+}
+
+#[test]
+fn replayed_edits_land_on_the_recorded_step() {
+    let path = std::env::temp_dir().join(format!("trajectory-edits-{}.ron", std::process::id()));
+    let mut game = arena();
+    game.app.add_plugins(ReplayPlugin {
+        mode: ReplayMode::Record(path.clone()),
+    });
+    track_without_bullet_time(&mut game);
+    // carve into the right wall right before the player works out where it will bounce off it.
+    assert!(game.step_until(100, |w| w.resource::<StepCount>().0 == 49));
+    game.set_mouse(Vec2::new(300.0, -70.0));
+    game.click(MouseButton::Right);
+    assert!(game.step_until(1000, |w| w.resource::<StepCount>().0 == 200));
+    let recorded = game.app.world.resource::<Track>().0.clone();
+    assert!(recorded[59].unwrap().x > 310.0, "the player should move into the hole");
+
+    let replay = Replay::load(&path).expect("replay should be saved while recording");
+    fs::remove_file(&path).unwrap();
+    assert_eq!(replay.edits.len(), 1);
+
+    // four steps run in every frame, so the edit lands between two steps of the same frame.
+    let mut game = Headless::with_frame_time(replay.level.clone(), TIMESTEP * 4);
+    game.app.add_plugins(ReplayPlugin {
+        mode: ReplayMode::Play(replay),
+    });
+    track_without_bullet_time(&mut game);
+    assert!(game.step_until(1000, |w| w.resource::<StepCount>().0 >= 200));
+    let replayed = &game.app.world.resource::<Track>().0;
+    assert_eq!(replayed[..recorded.len()], recorded[..]);
+}